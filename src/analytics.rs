@@ -0,0 +1,368 @@
+//! Training summaries computed from logged sets.
+//!
+//! Warm-up sets don't reflect working capacity, so volume, estimated one-rep maxes,
+//! best sets and effort trends skip them; only the session and set counts of
+//! [`exercise_stats`] include them. Volume and one-rep maxes also skip sets without
+//! reps (timed or distance work). Weights are turned into the load actually moved
+//! through [`Loads`], which knows which exercises are done with bodyweight.
+
+use crate::exercise::{Exercise, Loading};
+use crate::set::Set;
+use crate::user_profile::UserProfile;
+use crate::workout_session::WorkoutSession;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Turns the weight recorded on a set into the load moved, in kilograms.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Loads {
+    loading: HashMap<String, Loading>,
+    bodyweight: Option<f32>,
+}
+
+impl Loads {
+    pub fn new<'a>(
+        exercises: impl IntoIterator<Item = &'a Exercise>,
+        bodyweight: Option<f32>,
+    ) -> Loads {
+        let loading = exercises
+            .into_iter()
+            .map(|exercise| (exercise.get_name().clone(), exercise.get_loading()))
+            .collect();
+        Loads {
+            loading,
+            bodyweight,
+        }
+    }
+    /// Uses `user`'s exercises, including the built-in ones, and their bodyweight
+    /// when `session` started.
+    pub fn for_session(user: &UserProfile, session: &WorkoutSession) -> Loads {
+        Loads::new(
+            user.all_exercises(),
+            user.get_bodyweight_at(session.get_started_at()),
+        )
+    }
+    /// Unknown exercises count as externally loaded.
+    pub fn loading(&self, exercise: &str) -> Loading {
+        self.loading.get(exercise).copied().unwrap_or_default()
+    }
+    pub fn get_bodyweight(&self) -> Option<f32> {
+        self.bodyweight
+    }
+    /// `None` for a bodyweight exercise when no bodyweight has been logged.
+    pub fn load(&self, set: &Set) -> Option<f32> {
+        self.loading(set.get_exercise())
+            .load(set.get_weight(), self.bodyweight)
+    }
+}
+
+/// The sets that count towards training statistics, i.e. everything but warm-ups.
+pub fn working_sets<'a>(sets: impl IntoIterator<Item = &'a Set>) -> impl Iterator<Item = &'a Set> {
+    sets.into_iter().filter(|set| !set.get_kind().is_warm_up())
+}
+
+/// Total load moved (reps times load) across the working sets. Sets whose load
+/// isn't known are left out.
+pub fn volume<'a>(sets: impl IntoIterator<Item = &'a Set>, loads: &Loads) -> f32 {
+    working_sets(sets)
+        .filter_map(|set| Some(set.get_reps()? as f32 * loads.load(set)?))
+        .sum()
+}
+
+/// Estimated one-rep max of a single set using the Epley formula.
+pub fn estimated_one_rep_max(set: &Set, loads: &Loads) -> Option<f32> {
+    let load = loads.load(set)?;
+    Some(match set.get_reps()? {
+        0 => 0.0,
+        1 => load,
+        reps => load * (1.0 + reps as f32 / 30.0),
+    })
+}
+
+/// The highest estimated one-rep max among the working sets of `exercise`.
+pub fn best_estimated_one_rep_max<'a>(
+    sets: impl IntoIterator<Item = &'a Set>,
+    exercise: &str,
+    loads: &Loads,
+) -> Option<f32> {
+    working_sets(sets)
+        .filter(|set| set.get_exercise() == exercise)
+        .filter_map(|set| estimated_one_rep_max(set, loads))
+        .reduce(f32::max)
+}
+
+/// How much one exercise has been done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExerciseStats<'a> {
+    /// Sessions with at least one set of the exercise, warm-ups included.
+    pub sessions: usize,
+    /// Every set of the exercise, warm-ups included.
+    pub sets: usize,
+    pub last_performed: Option<DateTime<Utc>>,
+    /// The working set with the highest estimated one-rep max. Sets without a
+    /// known load are ranked by distance, then duration, then reps.
+    pub best_set: Option<&'a Set>,
+}
+
+/// Usage of `exercise` across all of `user`'s sessions.
+pub fn exercise_stats<'a>(user: &'a UserProfile, exercise: &str) -> ExerciseStats<'a> {
+    let mut stats = ExerciseStats {
+        sessions: 0,
+        sets: 0,
+        last_performed: None,
+        best_set: None,
+    };
+    let mut best = None;
+    for session in user.get_workouts() {
+        let sets: Vec<&Set> = session
+            .get_sets()
+            .iter()
+            .filter(|set| set.get_exercise() == exercise)
+            .collect();
+        if sets.is_empty() {
+            continue;
+        }
+        stats.sessions += 1;
+        stats.sets += sets.len();
+        let performed_at = sets
+            .iter()
+            .filter_map(|set| set.get_performed_at())
+            .max()
+            .unwrap_or(session.get_started_at());
+        stats.last_performed = stats.last_performed.max(Some(performed_at));
+
+        let loads = Loads::for_session(user, session);
+        for set in working_sets(sets) {
+            let score = (
+                estimated_one_rep_max(set, &loads),
+                set.get_distance(),
+                set.get_duration(),
+                set.get_reps(),
+            );
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, set));
+            }
+        }
+    }
+    stats.best_set = best.map(|(_, set)| set);
+    stats
+}
+
+/// The average effort of one exercise in one session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffortPoint<'a> {
+    pub session: &'a WorkoutSession,
+    /// RIR ratings are converted to the RPE scale before averaging.
+    pub average_rpe: f32,
+    /// How many rated working sets the average covers.
+    pub sets: usize,
+}
+
+/// Average effort of `exercise` per session, in session order. Sessions without a
+/// rated working set of the exercise are left out.
+pub fn effort_trend<'a>(sessions: &'a [WorkoutSession], exercise: &str) -> Vec<EffortPoint<'a>> {
+    sessions
+        .iter()
+        .filter_map(|session| {
+            let ratings: Vec<f32> = working_sets(session.get_sets())
+                .filter(|set| set.get_exercise() == exercise)
+                .filter_map(|set| set.get_effort())
+                .map(|effort| effort.as_rpe())
+                .collect();
+            if ratings.is_empty() {
+                return None;
+            }
+            Some(EffortPoint {
+                session,
+                average_rpe: ratings.iter().sum::<f32>() / ratings.len() as f32,
+                sets: ratings.len(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exercise::Equipment;
+    use crate::set::{Effort, SetKind, SetValues};
+    use crate::units::Weight;
+
+    fn sets() -> Vec<Set> {
+        vec![
+            Set::new("squat".to_string(), 10, Weight::kg(60.0), SetKind::WarmUp),
+            Set::new("squat".to_string(), 5, Weight::kg(100.0), SetKind::Normal),
+            Set::new("squat".to_string(), 1, Weight::kg(120.0), SetKind::Failure),
+            Set::new("bench".to_string(), 8, Weight::kg(80.0), SetKind::Dropset),
+        ]
+    }
+
+    #[test]
+    fn test_volume_excludes_warm_ups() {
+        assert_eq!(volume(&sets(), &Loads::default()), 500.0 + 120.0 + 640.0);
+        assert_eq!(working_sets(&sets()).count(), 3);
+    }
+
+    #[test]
+    fn test_estimated_one_rep_max() {
+        let sets = sets();
+        let loads = Loads::default();
+        assert_eq!(
+            estimated_one_rep_max(&sets[1], &loads),
+            Some(100.0 * (1.0 + 5.0 / 30.0))
+        );
+        assert_eq!(estimated_one_rep_max(&sets[2], &loads), Some(120.0));
+        assert_eq!(
+            best_estimated_one_rep_max(&sets, "squat", &loads),
+            Some(120.0)
+        );
+        assert_eq!(best_estimated_one_rep_max(&sets, "deadlift", &loads), None);
+
+        // a heavy warm-up doesn't count
+        let warm_up = Set::new(
+            "deadlift".to_string(),
+            3,
+            Weight::kg(200.0),
+            SetKind::WarmUp,
+        );
+        assert_eq!(
+            best_estimated_one_rep_max([&warm_up], "deadlift", &loads),
+            None
+        );
+    }
+
+    #[test]
+    fn test_bodyweight_loads() {
+        let exercise = |name: &str, loading| {
+            Exercise::new(name.to_string(), None, vec![], Equipment::Other).with_loading(loading)
+        };
+        let exercises = [
+            exercise("push-up", Loading::Bodyweight),
+            exercise("dip", Loading::BodyweightPlus),
+            exercise("assisted pull-up", Loading::Assisted),
+        ];
+        let set = |name: &str, weight| {
+            Set::new(name.to_string(), 10, Weight::kg(weight), SetKind::Normal)
+        };
+        let sets = [
+            set("push-up", 0.0),
+            set("dip", 20.0),
+            set("assisted pull-up", 30.0),
+        ];
+
+        let loads = Loads::new(&exercises, Some(80.0));
+        let moved: Vec<Option<f32>> = sets.iter().map(|set| loads.load(set)).collect();
+        assert_eq!(moved, vec![Some(80.0), Some(100.0), Some(50.0)]);
+        assert_eq!(volume(&sets, &loads), 2300.0);
+
+        // without a logged bodyweight only the external load is known
+        let loads = Loads::new(&exercises, None);
+        assert_eq!(volume(&sets, &loads), 0.0);
+        assert_eq!(estimated_one_rep_max(&sets[1], &loads), None);
+    }
+
+    #[test]
+    fn test_sets_without_reps() {
+        let plank = Set::with_values(
+            "plank".to_string(),
+            &SetValues {
+                duration: Some(60),
+                ..SetValues::default()
+            },
+            SetKind::Normal,
+        );
+        let loads = Loads::default();
+        assert_eq!(volume([&plank], &loads), 0.0);
+        assert_eq!(estimated_one_rep_max(&plank, &loads), None);
+    }
+
+    #[test]
+    fn test_exercise_stats() {
+        let at = |hour| {
+            "2024-01-02T00:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::hours(hour)
+        };
+        let mut user = UserProfile::new("test".to_string());
+        user.add_workout(WorkoutSession::new(
+            1,
+            None,
+            vec![
+                Set::new("squat".to_string(), 5, Weight::kg(110.0), SetKind::Normal)
+                    .with_performed_at(Some(at(1))),
+            ],
+        ));
+        let sets = sets()
+            .into_iter()
+            .map(|set| set.with_performed_at(Some(at(2))))
+            .collect();
+        user.add_workout(WorkoutSession::new(2, None, sets));
+        let run = |distance| {
+            Set::with_values(
+                "run".to_string(),
+                &SetValues {
+                    distance: Some(distance),
+                    duration: Some(1500),
+                    ..SetValues::default()
+                },
+                SetKind::Normal,
+            )
+        };
+        user.add_workout(WorkoutSession::new(3, None, vec![run(5000.0), run(8000.0)]));
+
+        let stats = exercise_stats(&user, "squat");
+        assert_eq!((stats.sessions, stats.sets), (2, 4));
+        assert_eq!(stats.last_performed, Some(at(2)));
+        assert_eq!(stats.best_set.unwrap().get_weight(), Some(110.0));
+
+        let stats = exercise_stats(&user, "run");
+        assert_eq!(stats.best_set.unwrap().get_distance(), Some(8000.0));
+
+        let stats = exercise_stats(&user, "deadlift");
+        assert_eq!((stats.sessions, stats.last_performed), (0, None));
+        assert_eq!(stats.best_set, None);
+
+        // warm-ups are counted as done but never the best set
+        user.add_workout(WorkoutSession::new(
+            4,
+            None,
+            vec![
+                Set::new("squat".to_string(), 3, Weight::kg(140.0), SetKind::WarmUp),
+                Set::new("deadlift".to_string(), 5, Weight::kg(60.0), SetKind::WarmUp),
+            ],
+        ));
+        let stats = exercise_stats(&user, "squat");
+        assert_eq!((stats.sessions, stats.sets), (3, 5));
+        assert_eq!(stats.best_set.unwrap().get_weight(), Some(110.0));
+        let stats = exercise_stats(&user, "deadlift");
+        assert_eq!((stats.sessions, stats.sets), (1, 1));
+        assert_eq!(stats.best_set, None);
+    }
+
+    #[test]
+    fn test_effort_trend() {
+        let rated = |rpe, kind| {
+            Set::new("squat".to_string(), 5, Weight::kg(100.0), kind)
+                .with_effort(Some(Effort::Rpe(rpe)))
+        };
+        let sessions = vec![
+            WorkoutSession::new(
+                1,
+                None,
+                vec![
+                    rated(9.5, SetKind::WarmUp),
+                    rated(7.0, SetKind::Normal),
+                    Set::new("squat".to_string(), 5, Weight::kg(100.0), SetKind::Normal)
+                        .with_effort(Some(Effort::Rir(1))),
+                ],
+            ),
+            WorkoutSession::new(2, None, sets()),
+            WorkoutSession::new(3, None, vec![rated(8.5, SetKind::Amrap)]),
+        ];
+
+        let trend = effort_trend(&sessions, "squat");
+        let points: Vec<(u32, f32, usize)> = trend
+            .iter()
+            .map(|point| (point.session.get_id(), point.average_rpe, point.sets))
+            .collect();
+        assert_eq!(points, vec![(1, 8.0, 2), (3, 8.5, 1)]);
+    }
+}
@@ -0,0 +1,75 @@
+//! A log of the lifter's bodyweight, used to work out the load of bodyweight exercises.
+
+use crate::errors::Result;
+use crate::storage::Storage;
+use crate::units::Weight;
+use crate::user_profile::update_current_user;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BodyweightEntry {
+    recorded_at: DateTime<Utc>,
+    /// In kilograms.
+    weight: f32,
+}
+
+impl BodyweightEntry {
+    pub fn new(recorded_at: DateTime<Utc>, weight_kg: f32) -> BodyweightEntry {
+        BodyweightEntry {
+            recorded_at,
+            weight: weight_kg,
+        }
+    }
+    pub fn get_recorded_at(&self) -> DateTime<Utc> {
+        self.recorded_at
+    }
+    /// The bodyweight in kilograms.
+    pub fn get_weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// The bodyweight in effect at `at`: the latest entry recorded at or before it.
+/// Falls back to the earliest entry for times before the first weigh-in, so a
+/// session started just before logging a weight still picks it up.
+pub fn bodyweight_at(entries: &[BodyweightEntry], at: DateTime<Utc>) -> Option<f32> {
+    entries
+        .iter()
+        .filter(|entry| entry.recorded_at <= at)
+        .max_by_key(|entry| entry.recorded_at)
+        .or_else(|| entries.iter().min_by_key(|entry| entry.recorded_at))
+        .map(|entry| entry.weight)
+}
+
+/// Records the current profile's bodyweight as of now.
+pub fn log_bodyweight(storage: &dyn Storage, weight: Weight) -> Result<BodyweightEntry> {
+    let entry = BodyweightEntry::new(Utc::now(), weight.to_kg());
+    update_current_user(storage, |user| {
+        user.add_bodyweight(entry.clone());
+        Ok(())
+    })?;
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_bodyweight_at() {
+        let now = Utc::now();
+        let entries = vec![
+            BodyweightEntry::new(now, 80.0),
+            BodyweightEntry::new(now - Duration::days(30), 85.0),
+        ];
+        assert_eq!(bodyweight_at(&entries, now + Duration::days(1)), Some(80.0));
+        assert_eq!(bodyweight_at(&entries, now - Duration::days(1)), Some(85.0));
+        assert_eq!(
+            bodyweight_at(&entries, now - Duration::days(60)),
+            Some(85.0)
+        );
+        assert_eq!(bodyweight_at(&[], now), None);
+    }
+}
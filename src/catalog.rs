@@ -0,0 +1,89 @@
+//! Exercises bundled with the tool.
+//!
+//! Every profile can log sets of these without creating them first. They aren't
+//! copied into the profile: a profile only stores the exercises it created, and
+//! the built-in ones it edited, which replace the originals for that profile.
+
+use crate::exercise::{Equipment, Exercise, Loading, Metric, MuscleGroup};
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+const CATALOG: &str = include_str!("../data/exercises.json");
+
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    primary_muscles: Vec<MuscleGroup>,
+    #[serde(default)]
+    secondary_muscles: Vec<MuscleGroup>,
+    equipment: Equipment,
+    description: String,
+    #[serde(default)]
+    loading: Loading,
+    #[serde(default = "default_metrics")]
+    metrics: Vec<Metric>,
+}
+
+fn default_metrics() -> Vec<Metric> {
+    Metric::DEFAULT.to_vec()
+}
+
+/// The built-in exercises, in catalog order.
+pub fn catalog() -> &'static [Exercise] {
+    static EXERCISES: OnceLock<Vec<Exercise>> = OnceLock::new();
+    EXERCISES.get_or_init(|| {
+        let entries: Vec<Entry> =
+            serde_json::from_str(CATALOG).expect("the bundled exercise catalog is valid");
+        entries
+            .into_iter()
+            .map(|entry| {
+                Exercise::new(
+                    entry.name,
+                    Some(entry.description),
+                    entry.primary_muscles,
+                    entry.equipment,
+                )
+                .with_secondary_muscles(entry.secondary_muscles)
+                .with_aliases(entry.aliases)
+                .with_loading(entry.loading)
+                .with_metrics(entry.metrics)
+            })
+            .collect()
+    })
+}
+
+/// The built-in exercise called exactly `name`; aliases aren't considered.
+pub fn find(name: &str) -> Option<&'static Exercise> {
+    catalog()
+        .iter()
+        .find(|exercise| exercise.get_name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_catalog_names_are_unique() {
+        let mut names = HashSet::new();
+        for exercise in catalog() {
+            assert!(!exercise.get_metrics().is_empty());
+            for name in std::iter::once(exercise.get_name()).chain(exercise.get_aliases()) {
+                assert!(
+                    names.insert(name.to_ascii_lowercase()),
+                    "{} is used twice",
+                    name
+                );
+            }
+        }
+        assert_eq!(find("squat").unwrap().get_equipment(), Equipment::Barbell);
+        assert_eq!(
+            find("plank").unwrap().get_metrics(),
+            &vec![Metric::Duration]
+        );
+        assert_eq!(find("ohp"), None);
+    }
+}
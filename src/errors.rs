@@ -1,25 +1,74 @@
-use std::io::Error;
 use std::fmt;
+use std::io::Error;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum FileError {
     IoError(Error),
     SerdeError(serde_json::Error),
+    SqliteError(rusqlite::Error),
 }
 impl PartialEq for FileError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (FileError::IoError(e1), FileError::IoError(e2)) => e1.kind() == e2.kind(),
-            (FileError::SerdeError(e1), FileError::SerdeError(e2)) => e1.io_error_kind() == e2.io_error_kind(),
+            (FileError::SerdeError(e1), FileError::SerdeError(e2)) => {
+                e1.io_error_kind() == e2.io_error_kind()
+            }
+            (FileError::SqliteError(e1), FileError::SqliteError(e2)) => e1 == e2,
             _ => false,
         }
     }
 }
 
+/// What kind of thing a name typed by the user was looked up as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Exercise,
+    Session,
+    Profile,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ResultError {
     FileError(FileError),
-    OtherError(String),
+    LockTimeout(PathBuf),
+    InvalidPath(PathBuf),
+    BackupNotFound(PathBuf),
+    /// A file named on the command line doesn't exist.
+    FileNotFound(PathBuf),
+    NoDataDir,
+    /// A stored document is newer than this build understands.
+    UnsupportedSchemaVersion(u64),
+    InvalidDocument(String),
+    UserNotFound(String),
+    UserAlreadyExists(String),
+    NoCurrentUser,
+    SessionNotFound(String),
+    NoChosenSession,
+    ExerciseNotFound(String),
+    NoChosenExercise,
+    /// The exercise can't be deleted because logged sets refer to it.
+    ExerciseInUse {
+        exercise: String,
+        sets: usize,
+    },
+    /// No set with this number in the chosen session; sets are numbered from 1.
+    SetNotFound(usize),
+    /// The chosen session has no sets.
+    NoSets,
+    /// `query` matches more than one item; `candidates` describes each of them.
+    Ambiguous {
+        lookup: Lookup,
+        query: String,
+        candidates: Vec<String>,
+    },
+    /// A value given by the user is out of range.
+    InvalidInput(String),
+    /// The operation would overwrite or merge existing data.
+    Conflict(String),
+    /// The storage backend can't perform the operation.
+    Unsupported(String),
 }
 
 impl From<Error> for FileError {
@@ -34,25 +83,40 @@ impl From<serde_json::Error> for FileError {
     }
 }
 
-impl From<FileError> for ResultError {
-    fn from(error: FileError) -> Self {
-        ResultError::FileError(error)
+impl From<rusqlite::Error> for FileError {
+    fn from(error: rusqlite::Error) -> Self {
+        FileError::SqliteError(error)
     }
 }
 
-impl From<String> for ResultError {
-    fn from(error: String) -> Self {
-        ResultError::OtherError(error)
+impl From<rusqlite::Error> for ResultError {
+    fn from(error: rusqlite::Error) -> Self {
+        ResultError::FileError(FileError::SqliteError(error))
     }
 }
 
+impl From<FileError> for ResultError {
+    fn from(error: FileError) -> Self {
+        ResultError::FileError(error)
+    }
+}
 
 impl fmt::Display for FileError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             FileError::IoError(e) => write!(f, "IO error: {}", e),
             FileError::SerdeError(e) => write!(f, "Serialization error: {}", e),
-            // Handle other variants...
+            FileError::SqliteError(e) => write!(f, "SQLite error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FileError::IoError(e) => Some(e),
+            FileError::SerdeError(e) => Some(e),
+            FileError::SqliteError(e) => Some(e),
         }
     }
 }
@@ -61,7 +125,59 @@ impl fmt::Display for ResultError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ResultError::FileError(e) => write!(f, "File error: {}", e),
-            ResultError::OtherError(msg) => write!(f, "Other error: {}", msg),
+            ResultError::LockTimeout(path) => write!(
+                f,
+                "Timed out waiting for {}; is another workout command running?",
+                path.display()
+            ),
+            ResultError::InvalidPath(path) => write!(f, "Invalid path: {}", path.display()),
+            ResultError::BackupNotFound(path) => {
+                write!(f, "Backup {} does not exist", path.display())
+            }
+            ResultError::FileNotFound(path) => {
+                write!(f, "File {} does not exist", path.display())
+            }
+            ResultError::NoDataDir => write!(f, "Unable to find a data directory"),
+            ResultError::UnsupportedSchemaVersion(version) => write!(
+                f,
+                "Data was written by a newer version (schema version {})",
+                version
+            ),
+            ResultError::InvalidDocument(msg) => write!(f, "Invalid data file: {}", msg),
+            ResultError::UserNotFound(name) => write!(f, "User {} does not exist", name),
+            ResultError::UserAlreadyExists(name) => write!(f, "User {} already exists", name),
+            ResultError::NoCurrentUser => write!(f, "No user profile chosen"),
+            ResultError::SessionNotFound(name) => {
+                write!(f, "Workout session {} not found", name)
+            }
+            ResultError::NoChosenSession => write!(f, "No workout session chosen"),
+            ResultError::ExerciseNotFound(name) => write!(f, "Exercise {} not found", name),
+            ResultError::NoChosenExercise => write!(f, "No exercise chosen"),
+            ResultError::ExerciseInUse { exercise, sets } => {
+                write!(f, "Exercise {} is used by {} logged set(s)", exercise, sets)
+            }
+            ResultError::SetNotFound(number) => write!(f, "Set {} not found", number),
+            ResultError::NoSets => write!(f, "The workout session has no sets"),
+            ResultError::Ambiguous {
+                query, candidates, ..
+            } => write!(
+                f,
+                "{} is ambiguous, it could be any of: {}",
+                query,
+                candidates.join(", ")
+            ),
+            ResultError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            ResultError::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            ResultError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ResultError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResultError::FileError(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -1,70 +1,1045 @@
-use crate::errors::{ResultError, Result};
-use crate::user_profile::get_current_user;
+use crate::catalog;
+use crate::errors::{Result, ResultError};
+use crate::resolve::allowed_typos;
+use crate::set::SetValues;
+use crate::storage::Storage;
+use crate::user_profile::{get_current_user, update_current_user};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How the weight recorded on a set relates to the load actually moved.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Loading {
+    /// Barbells, dumbbells, machines: the recorded weight is the load.
+    #[default]
+    External,
+    /// Push-ups, plain pull-ups: the load is the lifter's bodyweight.
+    Bodyweight,
+    /// Weighted pull-ups or dips: bodyweight plus the recorded weight.
+    BodyweightPlus,
+    /// Assisted machines and bands: bodyweight minus the recorded assistance.
+    Assisted,
+}
+
+impl Loading {
+    pub const ALL: [Loading; 4] = [
+        Loading::External,
+        Loading::Bodyweight,
+        Loading::BodyweightPlus,
+        Loading::Assisted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Loading::External => "external",
+            Loading::Bodyweight => "bodyweight",
+            Loading::BodyweightPlus => "bodyweight-plus",
+            Loading::Assisted => "assisted",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Loading> {
+        Loading::ALL
+            .into_iter()
+            .find(|loading| loading.as_str() == name)
+    }
+    /// The load moved in kilograms, given the recorded `weight` and the lifter's
+    /// `bodyweight`. `None` if the exercise depends on a weight or bodyweight that
+    /// isn't known; a missing weight on a bodyweight exercise counts as zero.
+    pub fn load(&self, weight: Option<f32>, bodyweight: Option<f32>) -> Option<f32> {
+        let extra = weight.unwrap_or(0.0);
+        match self {
+            Loading::External => weight,
+            Loading::Bodyweight => bodyweight,
+            Loading::BodyweightPlus => bodyweight.map(|bodyweight| bodyweight + extra),
+            Loading::Assisted => bodyweight.map(|bodyweight| (bodyweight - extra).max(0.0)),
+        }
+    }
+}
+
+impl fmt::Display for Loading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something measured on each set of an exercise. Planks track a duration, runs
+/// a distance and a duration, most lifts reps and weight.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Metric {
+    Reps,
+    Weight,
+    Duration,
+    Distance,
+    Calories,
+    HeartRate,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 6] = [
+        Metric::Reps,
+        Metric::Weight,
+        Metric::Duration,
+        Metric::Distance,
+        Metric::Calories,
+        Metric::HeartRate,
+    ];
+    /// What a lift tracks unless told otherwise.
+    pub const DEFAULT: [Metric; 2] = [Metric::Reps, Metric::Weight];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Metric::Reps => "reps",
+            Metric::Weight => "weight",
+            Metric::Duration => "duration",
+            Metric::Distance => "distance",
+            Metric::Calories => "calories",
+            Metric::HeartRate => "heart-rate",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Metric> {
+        Metric::ALL
+            .into_iter()
+            .find(|metric| metric.as_str() == name)
+    }
+    /// Calories and heart rate come from a watch or machine and may be missing
+    /// even when an exercise tracks them.
+    pub fn is_optional(&self) -> bool {
+        matches!(self, Metric::Calories | Metric::HeartRate)
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A muscle group an exercise trains.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum MuscleGroup {
+    Chest,
+    Back,
+    Lats,
+    Traps,
+    LowerBack,
+    Shoulders,
+    Biceps,
+    Triceps,
+    Forearms,
+    Core,
+    Quads,
+    Hamstrings,
+    Glutes,
+    Calves,
+    Adductors,
+}
+
+impl MuscleGroup {
+    pub const ALL: [MuscleGroup; 15] = [
+        MuscleGroup::Chest,
+        MuscleGroup::Back,
+        MuscleGroup::Lats,
+        MuscleGroup::Traps,
+        MuscleGroup::LowerBack,
+        MuscleGroup::Shoulders,
+        MuscleGroup::Biceps,
+        MuscleGroup::Triceps,
+        MuscleGroup::Forearms,
+        MuscleGroup::Core,
+        MuscleGroup::Quads,
+        MuscleGroup::Hamstrings,
+        MuscleGroup::Glutes,
+        MuscleGroup::Calves,
+        MuscleGroup::Adductors,
+    ];
+    /// Other common names, accepted on input.
+    const SYNONYMS: [(&'static str, MuscleGroup); 24] = [
+        ("pecs", MuscleGroup::Chest),
+        ("pectorals", MuscleGroup::Chest),
+        ("upper-back", MuscleGroup::Back),
+        ("lat", MuscleGroup::Lats),
+        ("latissimus", MuscleGroup::Lats),
+        ("trap", MuscleGroup::Traps),
+        ("trapezius", MuscleGroup::Traps),
+        ("erectors", MuscleGroup::LowerBack),
+        ("shoulder", MuscleGroup::Shoulders),
+        ("delts", MuscleGroup::Shoulders),
+        ("deltoids", MuscleGroup::Shoulders),
+        ("bicep", MuscleGroup::Biceps),
+        ("tricep", MuscleGroup::Triceps),
+        ("forearm", MuscleGroup::Forearms),
+        ("grip", MuscleGroup::Forearms),
+        ("abs", MuscleGroup::Core),
+        ("abdominals", MuscleGroup::Core),
+        ("obliques", MuscleGroup::Core),
+        ("quadriceps", MuscleGroup::Quads),
+        ("hams", MuscleGroup::Hamstrings),
+        ("glute", MuscleGroup::Glutes),
+        ("calf", MuscleGroup::Calves),
+        ("adductor", MuscleGroup::Adductors),
+        ("groin", MuscleGroup::Adductors),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MuscleGroup::Chest => "chest",
+            MuscleGroup::Back => "back",
+            MuscleGroup::Lats => "lats",
+            MuscleGroup::Traps => "traps",
+            MuscleGroup::LowerBack => "lower-back",
+            MuscleGroup::Shoulders => "shoulders",
+            MuscleGroup::Biceps => "biceps",
+            MuscleGroup::Triceps => "triceps",
+            MuscleGroup::Forearms => "forearms",
+            MuscleGroup::Core => "core",
+            MuscleGroup::Quads => "quads",
+            MuscleGroup::Hamstrings => "hamstrings",
+            MuscleGroup::Glutes => "glutes",
+            MuscleGroup::Calves => "calves",
+            MuscleGroup::Adductors => "adductors",
+        }
+    }
+    /// Accepts the canonical names and common synonyms, ignoring case and treating
+    /// spaces and underscores like dashes.
+    pub fn from_name(name: &str) -> Option<MuscleGroup> {
+        let name = normalize(name);
+        MuscleGroup::ALL
+            .into_iter()
+            .map(|muscle| (muscle.as_str(), muscle))
+            .chain(MuscleGroup::SYNONYMS)
+            .find(|(candidate, _)| *candidate == name)
+            .map(|(_, muscle)| muscle)
+    }
+    /// Like `from_name`, but explains what went wrong, suggesting the closest name.
+    pub fn parse(name: &str) -> Result<MuscleGroup> {
+        MuscleGroup::from_name(name).ok_or_else(|| {
+            let names = MuscleGroup::ALL
+                .into_iter()
+                .map(|muscle| (muscle.as_str(), muscle))
+                .chain(MuscleGroup::SYNONYMS);
+            unknown(
+                "muscle group",
+                name,
+                names,
+                MuscleGroup::ALL.map(|x| x.as_str()),
+            )
+        })
+    }
+}
+
+impl fmt::Display for MuscleGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What an exercise is performed with.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Equipment {
+    Barbell,
+    Dumbbell,
+    Kettlebell,
+    Machine,
+    Cable,
+    Bodyweight,
+    Band,
+    #[default]
+    Other,
+}
+
+impl Equipment {
+    pub const ALL: [Equipment; 8] = [
+        Equipment::Barbell,
+        Equipment::Dumbbell,
+        Equipment::Kettlebell,
+        Equipment::Machine,
+        Equipment::Cable,
+        Equipment::Bodyweight,
+        Equipment::Band,
+        Equipment::Other,
+    ];
+    /// Other common names, accepted on input.
+    const SYNONYMS: [(&'static str, Equipment); 14] = [
+        ("bb", Equipment::Barbell),
+        ("ez-bar", Equipment::Barbell),
+        ("trap-bar", Equipment::Barbell),
+        ("db", Equipment::Dumbbell),
+        ("dumbbells", Equipment::Dumbbell),
+        ("kb", Equipment::Kettlebell),
+        ("smith-machine", Equipment::Machine),
+        ("cables", Equipment::Cable),
+        ("pulley", Equipment::Cable),
+        ("none", Equipment::Bodyweight),
+        ("body-weight", Equipment::Bodyweight),
+        ("bw", Equipment::Bodyweight),
+        ("bands", Equipment::Band),
+        ("resistance-band", Equipment::Band),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Equipment::Barbell => "barbell",
+            Equipment::Dumbbell => "dumbbell",
+            Equipment::Kettlebell => "kettlebell",
+            Equipment::Machine => "machine",
+            Equipment::Cable => "cable",
+            Equipment::Bodyweight => "bodyweight",
+            Equipment::Band => "band",
+            Equipment::Other => "other",
+        }
+    }
+    /// Accepts the canonical names and common synonyms, like `MuscleGroup::from_name`.
+    pub fn from_name(name: &str) -> Option<Equipment> {
+        let name = normalize(name);
+        Equipment::ALL
+            .into_iter()
+            .map(|equipment| (equipment.as_str(), equipment))
+            .chain(Equipment::SYNONYMS)
+            .find(|(candidate, _)| *candidate == name)
+            .map(|(_, equipment)| equipment)
+    }
+    /// Like `from_name`, but explains what went wrong, suggesting the closest name.
+    pub fn parse(name: &str) -> Result<Equipment> {
+        Equipment::from_name(name).ok_or_else(|| {
+            let names = Equipment::ALL
+                .into_iter()
+                .map(|equipment| (equipment.as_str(), equipment))
+                .chain(Equipment::SYNONYMS);
+            unknown(
+                "kind of equipment",
+                name,
+                names,
+                Equipment::ALL.map(|x| x.as_str()),
+            )
+        })
+    }
+}
+
+impl fmt::Display for Equipment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_ascii_lowercase().replace([' ', '_'], "-")
+}
+
+/// The error for an unknown `name`: suggests the value whose name or synonym is
+/// closest to it, or lists the canonical `choices` if nothing is close.
+fn unknown<T: fmt::Display>(
+    what: &str,
+    name: &str,
+    names: impl IntoIterator<Item = (&'static str, T)>,
+    choices: impl IntoIterator<Item = &'static str>,
+) -> ResultError {
+    let normalized = normalize(name);
+    let closest = names
+        .into_iter()
+        .map(|(candidate, value)| (strsim::jaro_winkler(&normalized, candidate), value))
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .filter(|(similarity, _)| *similarity >= 0.8);
+    ResultError::InvalidInput(match closest {
+        Some((_, value)) => format!("{} is not a {}; did you mean {}?", name, what, value),
+        None => format!(
+            "{} is not a {}; use one of: {}",
+            name,
+            what,
+            choices.into_iter().collect::<Vec<_>>().join(", ")
+        ),
+    })
+}
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Exercise {
     name: String,
+    /// Other names the exercise is known by, e.g. `ohp` for the overhead press.
+    aliases: Vec<String>,
     description: String,
-    muscle_groups: Vec<String>,
-    equipment: String,
+    /// The muscles the exercise mainly trains.
+    primary_muscles: Vec<MuscleGroup>,
+    /// Muscles that assist without being the focus.
+    secondary_muscles: Vec<MuscleGroup>,
+    equipment: Equipment,
+    loading: Loading,
+    metrics: Vec<Metric>,
+    /// Archived exercises keep their history but can't be chosen for new sets.
+    archived: bool,
 }
 
 impl Exercise {
-    fn new(
+    pub fn new(
         name: String,
         description: Option<String>,
-        muscle_groups: Vec<String>,
-        equipment: String,
+        primary_muscles: Vec<MuscleGroup>,
+        equipment: Equipment,
     ) -> Exercise {
         Exercise {
             name,
+            aliases: Vec::new(),
             description: description.unwrap_or_default(),
-            muscle_groups,
+            primary_muscles,
+            secondary_muscles: Vec::new(),
             equipment,
+            loading: Loading::default(),
+            metrics: Metric::DEFAULT.to_vec(),
+            archived: false,
         }
     }
+    pub fn with_loading(mut self, loading: Loading) -> Exercise {
+        self.loading = loading;
+        self
+    }
+    pub fn with_metrics(mut self, metrics: Vec<Metric>) -> Exercise {
+        self.metrics = metrics;
+        self
+    }
+    pub fn with_secondary_muscles(mut self, muscles: Vec<MuscleGroup>) -> Exercise {
+        self.secondary_muscles = muscles;
+        self
+    }
+    pub fn with_aliases(mut self, aliases: Vec<String>) -> Exercise {
+        self.aliases = aliases;
+        self
+    }
     pub fn get_name(&self) -> &String {
         &self.name
     }
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+    pub fn get_aliases(&self) -> &Vec<String> {
+        &self.aliases
+    }
+    /// Whether `name` is this exercise's name or one of its aliases, ignoring case.
+    pub fn is_called(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+    pub fn get_description(&self) -> &String {
+        &self.description
+    }
+    pub fn get_primary_muscles(&self) -> &Vec<MuscleGroup> {
+        &self.primary_muscles
+    }
+    pub fn get_secondary_muscles(&self) -> &Vec<MuscleGroup> {
+        &self.secondary_muscles
+    }
+    /// Whether the exercise trains `muscle`, as a primary or secondary muscle.
+    pub fn works(&self, muscle: MuscleGroup) -> bool {
+        self.primary_muscles.contains(&muscle) || self.secondary_muscles.contains(&muscle)
+    }
+    pub fn get_equipment(&self) -> Equipment {
+        self.equipment
+    }
+    pub fn get_loading(&self) -> Loading {
+        self.loading
+    }
+    pub fn get_metrics(&self) -> &Vec<Metric> {
+        &self.metrics
+    }
+    pub fn is_archived(&self) -> bool {
+        self.archived
+    }
+    pub fn set_archived(&mut self, archived: bool) {
+        self.archived = archived;
+    }
+    pub fn tracks(&self, metric: Metric) -> bool {
+        self.metrics.contains(&metric)
+    }
+    pub fn apply(&mut self, edit: ExerciseEdit) {
+        if let Some(description) = edit.description {
+            self.description = description;
+        }
+        if let Some(muscles) = edit.primary_muscles {
+            self.primary_muscles = muscles;
+        }
+        if let Some(muscles) = edit.secondary_muscles {
+            self.secondary_muscles = muscles;
+        }
+        if let Some(equipment) = edit.equipment {
+            self.equipment = equipment;
+        }
+        if let Some(loading) = edit.loading {
+            self.loading = loading;
+        }
+    }
+    /// Rejects a muscle group listed twice, whether in the same list or as both a
+    /// primary and a secondary muscle.
+    fn check_muscles(&self) -> Result<()> {
+        let muscles: Vec<&MuscleGroup> = self
+            .primary_muscles
+            .iter()
+            .chain(&self.secondary_muscles)
+            .collect();
+        for (index, muscle) in muscles.iter().enumerate() {
+            if !muscles[..index].contains(muscle) {
+                continue;
+            }
+            let both =
+                self.primary_muscles.contains(muscle) && self.secondary_muscles.contains(muscle);
+            return Err(ResultError::InvalidInput(if both {
+                format!("{} can't be both a primary and a secondary muscle", muscle)
+            } else {
+                format!("{} is listed twice", muscle)
+            }));
+        }
+        Ok(())
+    }
+    /// Checks that `values` has every metric this exercise needs and nothing it
+    /// doesn't track.
+    pub fn validate(&self, values: &SetValues) -> Result<()> {
+        for metric in Metric::ALL {
+            if values.has(metric) && !self.tracks(metric) {
+                return Err(ResultError::InvalidInput(format!(
+                    "{} doesn't track {}",
+                    self.name, metric
+                )));
+            }
+            if !values.has(metric) && self.tracks(metric) && !metric.is_optional() {
+                return Err(ResultError::InvalidInput(format!(
+                    "{} needs {} on every set",
+                    self.name, metric
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
-pub fn create_exercise(
-    name: String,
-    description: Option<String>,
-    muscle_groups: Vec<String>,
-    equipment: String,
-) -> Result<()> {
-    Exercise::new(
-        name.clone(),
-        description.clone(),
-        muscle_groups.clone(),
-        equipment.clone(),
-    );
-    let mut user_profile = get_current_user()?;
-    user_profile.add_exercise(Exercise::new(name, description, muscle_groups, equipment));
-    user_profile.save()
+/// Changes to an exercise's definition; `None` leaves a field unchanged.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ExerciseEdit {
+    pub description: Option<String>,
+    pub primary_muscles: Option<Vec<MuscleGroup>>,
+    /// An empty list removes all of them.
+    pub secondary_muscles: Option<Vec<MuscleGroup>>,
+    pub equipment: Option<Equipment>,
+    /// Volume is worked out from the loading when it's shown, so this changes the
+    /// volume of the sets already logged too.
+    pub loading: Option<Loading>,
 }
 
-pub fn get_exercise(name: String) -> Result<Exercise> {
-    let user_profile = get_current_user()?;
+/// Narrows down a list of exercises; criteria left as `None` match everything.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExerciseFilter {
+    /// Matches the muscle as a primary or a secondary muscle.
+    pub muscle: Option<MuscleGroup>,
+    pub equipment: Option<Equipment>,
+    /// Matched against the name, aliases and description, tolerating typos.
+    pub search: Option<String>,
+}
 
-    for exercise in user_profile.get_exercises() {
-        if exercise.name == name {
-            return Result::Ok(exercise.clone());
+impl ExerciseFilter {
+    pub fn matches(&self, exercise: &Exercise) -> bool {
+        self.muscle.is_none_or(|muscle| exercise.works(muscle))
+            && self
+                .equipment
+                .is_none_or(|equipment| exercise.equipment == equipment)
+            && self
+                .search
+                .as_deref()
+                .is_none_or(|query| search_matches(exercise, query))
+    }
+}
+
+/// Whether every word of `query` starts a word of the exercise's name, aliases or
+/// description, or is a typo or two away from one.
+fn search_matches(exercise: &Exercise, query: &str) -> bool {
+    let text = std::iter::once(&exercise.name)
+        .chain(&exercise.aliases)
+        .chain(std::iter::once(&exercise.description))
+        .map(|text| text.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    query.to_lowercase().split_whitespace().all(|term| {
+        words.iter().any(|word| {
+            word.starts_with(term) || strsim::damerau_levenshtein(word, term) <= allowed_typos(term)
+        })
+    })
+}
+
+/// Adds `exercise` to the current profile. Its name and aliases can't be ones any
+/// exercise, built-in or the profile's own, already goes by; `edit_exercise`
+/// changes a built-in one instead.
+pub fn create_exercise(storage: &dyn Storage, exercise: Exercise) -> Result<()> {
+    if exercise.metrics.is_empty() {
+        return Err(ResultError::InvalidInput(
+            "an exercise has to track at least one metric".to_string(),
+        ));
+    }
+    exercise.check_muscles()?;
+    update_current_user(storage, |user_profile| {
+        for name in std::iter::once(&exercise.name).chain(&exercise.aliases) {
+            if let Some(existing) = user_profile.exercise_called(name, None) {
+                return Err(name_taken(name, existing));
+            }
         }
+        user_profile.add_exercise(exercise.clone());
+        Ok(())
+    })
+}
+
+/// Looks `name` up among the profile's exercises and the built-in catalog; see
+/// `UserProfile::resolve_exercise` for what it may be.
+pub fn get_exercise(storage: &dyn Storage, name: String) -> Result<Exercise> {
+    let user_profile = get_current_user(storage)?;
+    user_profile.resolve_exercise(&name).cloned()
+}
+
+/// Fails with `ExerciseInUse` while logged sets refer to the exercise, so history
+/// never points at a missing exercise; `archive_exercise` is the alternative.
+/// Deleting a profile's replacement of a built-in exercise brings the built-in back.
+pub fn delete_exercise(storage: &dyn Storage, name: &str) -> Result<()> {
+    update_current_user(storage, |user_profile| {
+        let built_in = catalog::find(name).is_some();
+        if user_profile.get_exercise(name).is_none() {
+            if built_in {
+                return Err(ResultError::Unsupported(format!(
+                    "{} is a built-in exercise; archive it instead",
+                    name
+                )));
+            }
+            return Err(ResultError::ExerciseNotFound(name.to_string()));
+        }
+        if built_in {
+            user_profile.remove_exercise(name);
+            return Ok(());
+        }
+        let sets = user_profile.count_sets_of(name);
+        if sets > 0 {
+            return Err(ResultError::ExerciseInUse {
+                exercise: name.to_string(),
+                sets,
+            });
+        }
+        user_profile.remove_exercise(name);
+        if user_profile.get_chosen_exercise().map(String::as_str) == Some(name) {
+            user_profile.clear_chosen_exercise();
+        }
+        Ok(())
+    })
+}
+
+/// Renames an exercise and every logged set of it; returns how many sets changed.
+pub fn rename_exercise(storage: &dyn Storage, old: &str, new: &str) -> Result<usize> {
+    update_current_user(storage, |user_profile| {
+        user_profile.rename_exercise(old, new)
+    })
+}
+
+/// Hides an exercise from new sets while keeping the sets already logged.
+pub fn archive_exercise(storage: &dyn Storage, name: &str) -> Result<()> {
+    update_current_user(storage, |user_profile| {
+        let name = user_profile.resolve_exercise_name(name)?;
+        user_profile
+            .customize_exercise(&name)
+            .ok_or(ResultError::ExerciseNotFound(name.clone()))?
+            .set_archived(true);
+        if user_profile.get_chosen_exercise() == Some(&name) {
+            user_profile.clear_chosen_exercise();
+        }
+        Ok(())
+    })
+}
+
+/// Changes the definition of exercise `name` and returns the result. Logged sets
+/// keep pointing at it, as neither its name nor its metrics can change here; a
+/// built-in exercise is copied into the profile first.
+pub fn edit_exercise(storage: &dyn Storage, name: &str, edit: ExerciseEdit) -> Result<Exercise> {
+    update_current_user(storage, |user_profile| {
+        let name = user_profile.resolve_exercise_name(name)?;
+        let mut edited = user_profile
+            .find_exercise(&name)
+            .ok_or(ResultError::ExerciseNotFound(name.clone()))?
+            .clone();
+        edited.apply(edit.clone());
+        edited.check_muscles()?;
+
+        let exercise = user_profile
+            .customize_exercise(&name)
+            .ok_or(ResultError::ExerciseNotFound(name.clone()))?;
+        *exercise = edited.clone();
+        Ok(edited)
+    })
+}
+
+pub fn unarchive_exercise(storage: &dyn Storage, name: &str) -> Result<()> {
+    update_current_user(storage, |user_profile| {
+        let name = user_profile.resolve_exercise_name(name)?;
+        user_profile
+            .customize_exercise(&name)
+            .ok_or(ResultError::ExerciseNotFound(name.clone()))?
+            .set_archived(false);
+        Ok(())
+    })
+}
+
+/// Returns the exercise's name, which may differ from `name` if that was an alias,
+/// an abbreviation or misspelled.
+pub fn choose_exercise(storage: &dyn Storage, name: String) -> Result<String> {
+    update_current_user(storage, |user_profile| {
+        let exercise = user_profile.resolve_exercise(&name)?;
+        if exercise.is_archived() {
+            return Err(archived(exercise.get_name()));
+        }
+        let name = exercise.get_name().clone();
+        user_profile.set_chosen_exercise(name.clone());
+        Ok(name)
+    })
+}
+
+/// The error for logging or choosing an archived exercise.
+pub fn archived(name: &str) -> ResultError {
+    ResultError::Conflict(format!(
+        "exercise {} is archived; run `unarchive-exercise {}` to use it again",
+        name, name
+    ))
+}
+
+/// The error for a new exercise name that `existing` already goes by.
+pub fn name_taken(name: &str, existing: &Exercise) -> ResultError {
+    if existing.get_name().eq_ignore_ascii_case(name) {
+        ResultError::Conflict(format!("exercise {} already exists", existing.get_name()))
+    } else {
+        ResultError::Conflict(format!(
+            "{} is already an alias of exercise {}",
+            name,
+            existing.get_name()
+        ))
     }
-    Result::Err(ResultError::OtherError("Exercise not found".to_string()))
 }
 
-pub fn delete_exercise(name: &str) -> Result<()> {
-    let mut user_profile = get_current_user()?;
-    user_profile.remove_exercise(name);
-    user_profile.save()
+pub fn get_chosen_exercise(storage: &dyn Storage) -> Result<String> {
+    let user_profile = get_current_user(storage)?;
+    user_profile
+        .get_chosen_exercise()
+        .cloned()
+        .ok_or(ResultError::NoChosenExercise)
 }
 
-pub fn choose_exercise(name: String) -> Result<()> {
-    let mut user_profile = get_current_user()?;
-    user_profile.set_chosen_exercise(name);
-    user_profile.save()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set::{create_set, SetKind};
+    use crate::storage::MemoryStorage;
+    use crate::units::Weight;
+    use crate::user_profile::{choose_profile, create_profile};
+    use crate::workout_session::start;
+
+    fn storage_with_exercises() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        create_profile(&storage, "test".to_string()).unwrap();
+        choose_profile(&storage, "test".to_string()).unwrap();
+        for name in ["box squat", "floor press"] {
+            let exercise = Exercise::new(name.to_string(), None, vec![], Equipment::Other);
+            create_exercise(&storage, exercise).unwrap();
+        }
+        storage
+    }
+
+    fn log(storage: &MemoryStorage, exercise: &str) -> Result<()> {
+        let values = SetValues {
+            reps: Some(5),
+            weight: Some(Weight::kg(100.0)),
+            ..SetValues::default()
+        };
+        create_set(storage, exercise.to_string(), values, SetKind::Normal, None).map(|_| ())
+    }
+
+    #[test]
+    fn test_parse_muscle_groups_and_equipment() {
+        assert_eq!(
+            MuscleGroup::parse("Lower back").unwrap(),
+            MuscleGroup::LowerBack
+        );
+        assert_eq!(MuscleGroup::parse("pecs").unwrap(), MuscleGroup::Chest);
+        assert_eq!(Equipment::parse("dumbbells").unwrap(), Equipment::Dumbbell);
+        assert_eq!(
+            MuscleGroup::parse("tricpes").unwrap_err().to_string(),
+            "Invalid input: tricpes is not a muscle group; did you mean triceps?"
+        );
+        assert!(Equipment::parse("xyz").unwrap_err().to_string().ends_with(
+            "use one of: barbell, dumbbell, kettlebell, machine, cable, bodyweight, band, other"
+        ));
+    }
+
+    #[test]
+    fn test_exercise_filter() {
+        let filter = |muscle, equipment, search: Option<&str>| ExerciseFilter {
+            muscle,
+            equipment,
+            search: search.map(str::to_string),
+        };
+        let names = |filter: ExerciseFilter| -> Vec<&str> {
+            catalog::catalog()
+                .iter()
+                .filter(|exercise| filter.matches(exercise))
+                .map(|exercise| exercise.get_name().as_str())
+                .collect()
+        };
+
+        assert_eq!(
+            names(filter(
+                Some(MuscleGroup::Back),
+                Some(Equipment::Barbell),
+                Some("row")
+            )),
+            vec!["barbell row"]
+        );
+        assert_eq!(
+            names(filter(None, None, Some("lat pulldwon"))),
+            vec!["lat pulldown"]
+        );
+        // aliases and descriptions are searched too
+        assert_eq!(
+            names(filter(None, None, Some("RDL"))),
+            vec!["romanian deadlift"]
+        );
+        assert!(names(filter(None, None, Some("forehead"))).contains(&"skull crusher"));
+        // secondary muscles count
+        assert!(names(filter(Some(MuscleGroup::Triceps), None, None)).contains(&"bench press"));
+        assert_eq!(
+            names(filter(None, None, None)).len(),
+            catalog::catalog().len()
+        );
+    }
+
+    #[test]
+    fn test_create_exercise_rejects_duplicates() {
+        let storage = storage_with_exercises();
+        let exercise = |name: &str, aliases: &[&str]| {
+            Exercise::new(name.to_string(), None, vec![], Equipment::Other)
+                .with_aliases(aliases.iter().map(|alias| alias.to_string()).collect())
+        };
+        assert_eq!(
+            create_exercise(&storage, exercise("Box Squat", &[])),
+            Err(ResultError::Conflict(
+                "exercise box squat already exists".to_string()
+            ))
+        );
+        assert_eq!(
+            create_exercise(&storage, exercise("squat", &[])),
+            Err(ResultError::Conflict(
+                "exercise squat already exists".to_string()
+            ))
+        );
+        assert_eq!(
+            create_exercise(&storage, exercise("back squat", &[])),
+            Err(ResultError::Conflict(
+                "back squat is already an alias of exercise squat".to_string()
+            ))
+        );
+        assert_eq!(
+            create_exercise(&storage, exercise("pin squat", &["Floor Press"])),
+            Err(ResultError::Conflict(
+                "exercise floor press already exists".to_string()
+            ))
+        );
+        assert_eq!(get_current_user(&storage).unwrap().get_exercises().len(), 2);
+        assert_eq!(
+            create_exercise(&storage, exercise("pin squat", &["pin"])),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_delete_exercise_in_use() {
+        let storage = storage_with_exercises();
+        start(&storage, None).unwrap();
+        log(&storage, "box squat").unwrap();
+        assert_eq!(
+            log(&storage, "sled push"),
+            Err(ResultError::ExerciseNotFound("sled push".to_string()))
+        );
+
+        assert_eq!(
+            delete_exercise(&storage, "box squat"),
+            Err(ResultError::ExerciseInUse {
+                exercise: "box squat".to_string(),
+                sets: 1
+            })
+        );
+        choose_exercise(&storage, "floor press".to_string()).unwrap();
+        delete_exercise(&storage, "floor press").unwrap();
+        assert_eq!(
+            get_chosen_exercise(&storage),
+            Err(ResultError::NoChosenExercise)
+        );
+        assert_eq!(
+            delete_exercise(&storage, "floor press"),
+            Err(ResultError::ExerciseNotFound("floor press".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_rename_exercise() {
+        let storage = storage_with_exercises();
+        start(&storage, None).unwrap();
+        log(&storage, "box squat").unwrap();
+        log(&storage, "floor press").unwrap();
+        log(&storage, "box squat").unwrap();
+        choose_exercise(&storage, "box squat".to_string()).unwrap();
+
+        assert_eq!(rename_exercise(&storage, "box squat", "pause squat"), Ok(2));
+        let user = get_current_user(&storage).unwrap();
+        let names: Vec<&str> = user.get_workouts()[0]
+            .get_sets()
+            .iter()
+            .map(|set| set.get_exercise().as_str())
+            .collect();
+        assert_eq!(names, vec!["pause squat", "floor press", "pause squat"]);
+        assert_eq!(get_chosen_exercise(&storage), Ok("pause squat".to_string()));
+        assert_eq!(
+            get_exercise(&storage, "box squat".to_string()),
+            Err(ResultError::ExerciseNotFound("box squat".to_string()))
+        );
+
+        assert!(matches!(
+            rename_exercise(&storage, "floor press", "pause squat"),
+            Err(ResultError::Conflict(_))
+        ));
+        assert_eq!(
+            rename_exercise(&storage, "box squat", "paused box squat"),
+            Err(ResultError::ExerciseNotFound("box squat".to_string()))
+        );
+
+        // built-in names and aliases are taken too, ignoring case
+        assert_eq!(
+            rename_exercise(&storage, "pause squat", "Bench Press"),
+            Err(ResultError::Conflict(
+                "exercise bench press already exists".to_string()
+            ))
+        );
+        assert_eq!(
+            rename_exercise(&storage, "pause squat", "back squat"),
+            Err(ResultError::Conflict(
+                "back squat is already an alias of exercise squat".to_string()
+            ))
+        );
+        assert!(matches!(
+            rename_exercise(&storage, "back squat", "my squat"),
+            Err(ResultError::Unsupported(_))
+        ));
+        assert_eq!(
+            rename_exercise(&storage, "pause squat", "Pause Squat"),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_edit_exercise() {
+        let storage = storage_with_exercises();
+        start(&storage, None).unwrap();
+        log(&storage, "box squat").unwrap();
+
+        let edit = ExerciseEdit {
+            description: Some("squat to a box".to_string()),
+            primary_muscles: Some(vec![MuscleGroup::Quads, MuscleGroup::Glutes]),
+            equipment: Some(Equipment::Barbell),
+            ..ExerciseEdit::default()
+        };
+        let edited = edit_exercise(&storage, "box squat", edit).unwrap();
+        assert_eq!(edited.get_description(), "squat to a box");
+        assert_eq!(edited.get_equipment(), Equipment::Barbell);
+        assert_eq!(get_exercise(&storage, "box squat".to_string()), Ok(edited));
+        let user = get_current_user(&storage).unwrap();
+        assert_eq!(user.count_sets_of("box squat"), 1);
+        assert_eq!(user.get_exercises().len(), 2);
+
+        // secondary muscles can be set and removed again
+        let edit = ExerciseEdit {
+            secondary_muscles: Some(vec![MuscleGroup::Hamstrings]),
+            ..ExerciseEdit::default()
+        };
+        let edited = edit_exercise(&storage, "box squat", edit).unwrap();
+        assert_eq!(
+            edited.get_secondary_muscles(),
+            &vec![MuscleGroup::Hamstrings]
+        );
+        let edit = ExerciseEdit {
+            secondary_muscles: Some(vec![]),
+            ..ExerciseEdit::default()
+        };
+        let edited = edit_exercise(&storage, "box squat", edit).unwrap();
+        assert!(edited.get_secondary_muscles().is_empty());
+        assert_eq!(
+            edited.get_primary_muscles(),
+            &vec![MuscleGroup::Quads, MuscleGroup::Glutes]
+        );
+
+        // a muscle can't be both primary and secondary, or listed twice
+        let edit = ExerciseEdit {
+            secondary_muscles: Some(vec![MuscleGroup::Glutes]),
+            ..ExerciseEdit::default()
+        };
+        assert_eq!(
+            edit_exercise(&storage, "box squat", edit),
+            Err(ResultError::InvalidInput(
+                "glutes can't be both a primary and a secondary muscle".to_string()
+            ))
+        );
+        let edit = ExerciseEdit {
+            primary_muscles: Some(vec![MuscleGroup::Chest, MuscleGroup::Chest]),
+            ..ExerciseEdit::default()
+        };
+        assert_eq!(
+            edit_exercise(&storage, "floor press", edit),
+            Err(ResultError::InvalidInput(
+                "chest is listed twice".to_string()
+            ))
+        );
+
+        // editing a built-in exercise keeps the rest of its definition
+        let edit = ExerciseEdit {
+            loading: Some(Loading::Bodyweight),
+            ..ExerciseEdit::default()
+        };
+        let dip = edit_exercise(&storage, "dips", edit).unwrap();
+        assert_eq!(dip.get_loading(), Loading::Bodyweight);
+        assert_eq!(
+            dip.get_aliases(),
+            catalog::find("dip").unwrap().get_aliases()
+        );
+
+        assert_eq!(
+            edit_exercise(&storage, "sled push", ExerciseEdit::default()),
+            Err(ResultError::ExerciseNotFound("sled push".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_archive_exercise() {
+        let storage = storage_with_exercises();
+        start(&storage, None).unwrap();
+        log(&storage, "box squat").unwrap();
+
+        archive_exercise(&storage, "box squat").unwrap();
+        assert!(get_exercise(&storage, "box squat".to_string())
+            .unwrap()
+            .is_archived());
+        assert!(matches!(
+            log(&storage, "box squat"),
+            Err(ResultError::Conflict(_))
+        ));
+        assert!(matches!(
+            choose_exercise(&storage, "box squat".to_string()),
+            Err(ResultError::Conflict(_))
+        ));
+
+        unarchive_exercise(&storage, "box squat").unwrap();
+        log(&storage, "box squat").unwrap();
+        choose_exercise(&storage, "box squat".to_string()).unwrap();
+    }
 }
@@ -0,0 +1,125 @@
+//! Consistency checks over stored profiles.
+//!
+//! The library refuses to create dangling references, but data written by older
+//! versions or edited by hand may still contain some; `check` reports them.
+
+use crate::errors::Result;
+use crate::storage::Storage;
+use crate::user_profile::{get_current_user, UserProfile};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A logged set names an exercise that is neither the profile's nor built in.
+    OrphanedSet {
+        session: u32,
+        /// Numbered from 1, as in `display-workout`.
+        set: usize,
+        exercise: String,
+    },
+    /// The chosen exercise doesn't exist.
+    MissingChosenExercise(String),
+    /// The chosen workout session doesn't exist.
+    MissingChosenSession(u32),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::OrphanedSet {
+                session,
+                set,
+                exercise,
+            } => write!(
+                f,
+                "set {} of session #{} is of unknown exercise {}",
+                set, session, exercise
+            ),
+            Problem::MissingChosenExercise(name) => {
+                write!(f, "the chosen exercise {} does not exist", name)
+            }
+            Problem::MissingChosenSession(id) => {
+                write!(f, "the chosen workout session #{} does not exist", id)
+            }
+        }
+    }
+}
+
+/// Every dangling reference in `user`, in session and set order.
+pub fn check_user(user: &UserProfile) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for session in user.get_workouts() {
+        for (index, set) in session.get_sets().iter().enumerate() {
+            if user.find_exercise(set.get_exercise()).is_none() {
+                problems.push(Problem::OrphanedSet {
+                    session: session.get_id(),
+                    set: index + 1,
+                    exercise: set.get_exercise().clone(),
+                });
+            }
+        }
+    }
+    if let Some(name) = user.get_chosen_exercise() {
+        if user.find_exercise(name).is_none() {
+            problems.push(Problem::MissingChosenExercise(name.clone()));
+        }
+    }
+    if let Some(id) = user.get_chosen_workout_session() {
+        if user.get_workout(id).is_none() {
+            problems.push(Problem::MissingChosenSession(id));
+        }
+    }
+    problems
+}
+
+/// Checks the current user's profile.
+pub fn check(storage: &dyn Storage) -> Result<Vec<Problem>> {
+    Ok(check_user(&get_current_user(storage)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exercise::{Equipment, Exercise};
+    use crate::set::{Set, SetKind};
+    use crate::units::Weight;
+    use crate::workout_session::WorkoutSession;
+
+    #[test]
+    fn test_check_user() {
+        let mut user = UserProfile::new("test".to_string());
+        assert_eq!(check_user(&user), vec![]);
+        user.add_exercise(Exercise::new(
+            "squat".to_string(),
+            None,
+            vec![],
+            Equipment::Other,
+        ));
+        let set = |name: &str| Set::new(name.to_string(), 5, Weight::kg(100.0), SetKind::Normal);
+        user.add_workout(WorkoutSession::new(
+            1,
+            None,
+            vec![set("squat"), set("sled push"), set("squat")],
+        ));
+
+        user.set_chosen_exercise("floor press".to_string());
+        user.set_chosen_workout_session(2);
+        let problems = check_user(&user);
+        assert_eq!(
+            problems,
+            vec![
+                Problem::OrphanedSet {
+                    session: 1,
+                    set: 2,
+                    exercise: "sled push".to_string()
+                },
+                Problem::MissingChosenExercise("floor press".to_string()),
+                Problem::MissingChosenSession(2),
+            ]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "set 2 of session #1 is of unknown exercise sled push"
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! Core of the gym tracker: user profiles, workout sessions, sets and exercises.
+//!
+//! Every operation returns [`errors::Result`], so the library can be embedded in
+//! other tools without panicking. The `workout` binary is a thin clap front end
+//! over these modules.
+
+pub mod analytics;
+pub mod bodyweight;
+pub mod catalog;
+pub mod errors;
+pub mod exercise;
+pub mod integrity;
+pub mod migrations;
+pub mod resolve;
+pub mod set;
+pub mod storage;
+pub mod units;
+pub mod user_profile;
+pub mod utils;
+pub mod workout_session;
+
+pub use errors::{Result, ResultError};
+pub use exercise::{Exercise, Loading};
+pub use set::{Set, SetKind};
+pub use storage::Storage;
+pub use units::{Weight, WeightUnit};
+pub use user_profile::{UserProfile, Users};
+pub use workout_session::WorkoutSession;
@@ -1,17 +1,40 @@
-use clap::{Arg, ArgAction, Command};
-
-mod errors;
-mod exercise;
-mod set;
-mod user_profile;
-mod utils;
-mod workout_session;
+use chrono::{DateTime, Duration, Local, Utc};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use gym_tracker::analytics::{self, Loads};
+use gym_tracker::errors::{Lookup, Result, ResultError};
+use gym_tracker::exercise::{
+    self, Equipment, Exercise, ExerciseEdit, ExerciseFilter, Loading, Metric, MuscleGroup,
+};
+use gym_tracker::set::{self, Effort, Set, SetEdit, SetKind, SetValues};
+use gym_tracker::storage::{self, JsonStorage, SqliteStorage, Storage, DATABASE_FILE_NAME};
+use gym_tracker::units::{self, Weight, WeightInput, WeightUnit};
+use gym_tracker::utils::BACKUP_COUNT;
+use gym_tracker::{bodyweight, integrity, user_profile, workout_session};
+use std::path::{Path, PathBuf};
+use std::process;
 
 fn cli() -> Command {
     Command::new("workout")
         .about("Controls a workout session")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(
+            Arg::new("data-dir")
+                .help("Where profiles are stored [default: $GYM_TRACKER_HOME or $XDG_DATA_HOME/gym_tracker]")
+                .long("data-dir")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("storage")
+                .help("The storage backend to use")
+                .long("storage")
+                .global(true)
+                .value_parser(["json", "sqlite"])
+                .default_value("json")
+                .action(ArgAction::Set),
+        )
         .subcommands([
             Command::new("create-profile")
                 .about("Creates a user profile")
@@ -33,6 +56,24 @@ fn cli() -> Command {
                         .index(1),
                 ),
             Command::new("current-profile").about("Shows the current user profile"),
+            Command::new("log-bodyweight")
+                .about("Records your bodyweight, used for bodyweight exercises")
+                .arg(
+                    Arg::new("weight")
+                        .help("Your bodyweight, e.g. 80kg or 176lb [default unit: the profile's]")
+                        .value_parser(WeightInput::parse)
+                        .required(true)
+                        .index(1),
+                ),
+            Command::new("set-unit")
+                .about("Sets the unit weights are shown and entered in")
+                .arg(
+                    Arg::new("unit")
+                        .help("The weight unit")
+                        .value_parser(WeightUnit::ALL.map(|unit| unit.as_str()))
+                        .required(true)
+                        .index(1),
+                ),
             Command::new("delete-profile")
                 .about("Deletes a user profile")
                 .arg(
@@ -44,23 +85,32 @@ fn cli() -> Command {
                 ),
         ])
         .subcommands([
-            Command::new("start-workout").about("Starts a workout session"),
+            Command::new("start-workout")
+                .about("Starts a workout session")
+                .arg(
+                    Arg::new("title")
+                        .help("An optional title for the workout session")
+                        .short('t')
+                        .long("title")
+                        .action(ArgAction::Set),
+                ),
             Command::new("choose-workout")
                 .about("Chooses a workout session")
                 .arg(
                     Arg::new("name")
-                        .help("The name of the workout session")
+                        .help("The id, title or date of the workout session")
                         .required(true)
                         .index(1),
                 ),
             Command::new("current-workout").about("Shows the current workout session"),
             Command::new("list-workouts").about("Lists all workout sessions"),
+            Command::new("finish-workout").about("Finishes the chosen workout session"),
             Command::new("delete-workout").about("Deletes a chosen workout session"),
             Command::new("display-workout").about("Displays a chosen workout session"),
         ])
         .subcommands([
             Command::new("create-exercise")
-                .about("Creates an exercise")
+                .about("Creates an exercise under a name no other exercise uses")
                 .arg(
                     Arg::new("name")
                         .help("The name of the exercise")
@@ -70,18 +120,29 @@ fn cli() -> Command {
                 )
                 .arg(
                     Arg::new("musclegroups")
-                        .help("The muscle groups of the exercise")
+                        .help("The muscle groups the exercise mainly works")
                         .short('m')
                         .required(true)
                         .action(ArgAction::Append)
-                        .value_delimiter(','),
+                        .value_delimiter(',')
+                        .value_parser(MuscleGroup::parse),
+                )
+                .arg(
+                    Arg::new("secondary")
+                        .help("The muscle groups the exercise also works")
+                        .short('s')
+                        .long("secondary")
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(MuscleGroup::parse),
                 )
                 .arg(
                     Arg::new("equipment")
                         .help("The equipment used for the exercise")
                         .short('e')
                         .action(ArgAction::Set)
-                        .required(true),
+                        .required(true)
+                        .value_parser(Equipment::parse),
                 )
                 .arg(
                     Arg::new("description")
@@ -89,16 +150,163 @@ fn cli() -> Command {
                         .short('d')
                         .required(false)
                         .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("loading")
+                        .help("How the recorded weight relates to the load moved")
+                        .short('l')
+                        .long("loading")
+                        .value_parser(Loading::ALL.map(|loading| loading.as_str()))
+                        .default_value("external")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("aliases")
+                        .help("Other names for the exercise, comma separated")
+                        .short('a')
+                        .long("aliases")
+                        .action(ArgAction::Append)
+                        .value_delimiter(','),
+                )
+                .arg(
+                    Arg::new("metrics")
+                        .help("What each set records, comma separated")
+                        .long("metrics")
+                        .value_parser(Metric::ALL.map(|metric| metric.as_str()))
+                        .value_delimiter(',')
+                        .default_values(Metric::DEFAULT.map(|metric| metric.as_str()))
+                        .action(ArgAction::Append),
+                ),
+            Command::new("list-exercises")
+                .about("Lists the built-in exercises and your own")
+                .arg(
+                    Arg::new("custom")
+                        .help("Only list the exercises you created")
+                        .long("custom")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("muscle")
+                        .help("Only list exercises working this muscle group")
+                        .long("muscle")
+                        .action(ArgAction::Set)
+                        .value_parser(MuscleGroup::parse),
+                )
+                .arg(
+                    Arg::new("equipment")
+                        .help("Only list exercises using this equipment")
+                        .long("equipment")
+                        .action(ArgAction::Set)
+                        .value_parser(Equipment::parse),
+                )
+                .arg(
+                    Arg::new("search")
+                        .help("Words to look for in the name, aliases and description")
+                        .long("search")
+                        .action(ArgAction::Set),
+                ),
+            Command::new("show-exercise")
+                .about("Shows an exercise and how much you have done it")
+                .arg(
+                    Arg::new("name")
+                        .help("The name of the exercise")
+                        .required(true)
+                        .index(1),
+                ),
+            Command::new("delete-exercise")
+                .about("Deletes an exercise that no logged set uses")
+                .arg(
+                    Arg::new("name")
+                        .help("The name of the exercise")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("archive")
+                        .help("Archive the exercise instead, keeping its logged sets")
+                        .long("archive")
+                        .action(ArgAction::SetTrue),
+                ),
+            Command::new("rename-exercise")
+                .about("Renames one of your exercises, including in past workout sessions")
+                .arg(
+                    Arg::new("old")
+                        .help("The current name of the exercise")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("new")
+                        .help("The new name")
+                        .required(true)
+                        .index(2),
+                ),
+            Command::new("edit-exercise")
+                .about("Changes an exercise; logged sets keep referring to it")
+                .arg(
+                    Arg::new("name")
+                        .help("The name of the exercise")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("musclegroups")
+                        .help("The muscle groups the exercise mainly works")
+                        .short('m')
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(MuscleGroup::parse),
+                )
+                .arg(
+                    Arg::new("secondary")
+                        .help("The muscle groups the exercise also works")
+                        .short('s')
+                        .long("secondary")
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(MuscleGroup::parse),
+                )
+                .arg(
+                    Arg::new("equipment")
+                        .help("The equipment used for the exercise")
+                        .short('e')
+                        .action(ArgAction::Set)
+                        .value_parser(Equipment::parse),
+                )
+                .arg(
+                    Arg::new("description")
+                        .help("The description of the exercise")
+                        .short('d')
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("clear-secondary")
+                        .help("Remove all secondary muscle groups")
+                        .long("clear-secondary")
+                        .conflicts_with("secondary")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("loading")
+                        .help(
+                            "How the recorded weight relates to the load moved; \
+                             also changes the volume of the sets already logged",
+                        )
+                        .short('l')
+                        .long("loading")
+                        .value_parser(Loading::ALL.map(|loading| loading.as_str()))
+                        .action(ArgAction::Set),
                 ),
-            Command::new("list-exercises").about("Lists all exercises"),
-            Command::new("delete-exercise").about("Deletes an exercise")
+            Command::new("unarchive-exercise")
+                .about("Makes an archived exercise available again")
                 .arg(
                     Arg::new("name")
                         .help("The name of the exercise")
                         .required(true)
                         .index(1),
                 ),
-            Command::new("choose-exercise").about("Chooses an exercise")
+            Command::new("choose-exercise")
+                .about("Chooses an exercise")
                 .arg(
                     Arg::new("name")
                         .help("The name of the exercise")
@@ -107,127 +315,909 @@ fn cli() -> Command {
                 ),
             Command::new("current-exercise").about("Shows the current exercise"),
         ])
+        .subcommands([Command::new("check")
+            .about("Reports sets and choices that refer to missing exercises or sessions")])
+        .subcommands([Command::new("restore-backup")
+            .about("Rolls the profiles back to a previous version")
+            .arg(
+                Arg::new("generation")
+                    .help("Which backup to restore, 1 being the most recent")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("1")
+                    .index(1),
+            )])
+        .subcommands([Command::new("import-json")
+            .about("Imports profiles from a JSON file into the SQLite database")
+            .arg(
+                Arg::new("file")
+                    .help("The JSON file to import")
+                    .default_value("user_profile.json")
+                    .index(1),
+            )])
         .subcommands([Command::new("add-set")
             .about("Creates a set for the current workout session")
             .arg(
                 Arg::new("reps")
                     .help("The number of reps")
-                    .required(true)
+                    .value_parser(clap::value_parser!(u32))
                     .index(1),
             )
             .arg(
                 Arg::new("weight")
-                    .help("The weight lifted")
-                    .required(true)
+                    .help("The weight lifted, e.g. 100kg or 225lb [default unit: the profile's]")
+                    .value_parser(WeightInput::parse)
                     .index(2),
+            )
+            .arg(
+                Arg::new("weight-option")
+                    .help("The weight, for exercises that don't count reps, e.g. a carry")
+                    .short('w')
+                    .long("weight")
+                    .value_name("WEIGHT")
+                    .value_parser(WeightInput::parse)
+                    .conflicts_with("weight")
+                    .action(ArgAction::Set),
+            )
+            .args(metric_args())
+            .arg(
+                Arg::new("kind")
+                    .help("The kind of set")
+                    .short('k')
+                    .long("kind")
+                    .value_parser(SetKind::ALL.map(|kind| kind.as_str()))
+                    .default_value("normal")
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("dropset")
+                    .help("Shorthand for --kind dropset")
+                    .short('d')
+                    .long("dropset")
+                    .conflicts_with("kind")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("rpe")
+                    .help("Rating of perceived exertion, 6 to 10 in steps of 0.5")
+                    .long("rpe")
+                    .value_parser(clap::value_parser!(f32))
+                    .conflicts_with("rir")
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("rir")
+                    .help("Reps in reserve, 0 to 4")
+                    .long("rir")
+                    .value_parser(clap::value_parser!(u32))
+                    .action(ArgAction::Set),
             )])
+        .subcommands([
+            Command::new("edit-set")
+                .about("Corrects a set of the current workout session")
+                .arg(
+                    Arg::new("index")
+                        .help("The number of the set, as shown by display-workout")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("reps")
+                        .help("The number of reps")
+                        .short('r')
+                        .long("reps")
+                        .value_parser(clap::value_parser!(u32))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("weight")
+                        .help("The weight lifted, e.g. 100kg or 225lb [default unit: the profile's]")
+                        .short('w')
+                        .long("weight")
+                        .value_parser(WeightInput::parse)
+                        .action(ArgAction::Set),
+                )
+                .args(metric_args())
+                .arg(
+                    Arg::new("exercise")
+                        .help("The exercise performed")
+                        .short('e')
+                        .long("exercise")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("kind")
+                        .help("The kind of set")
+                        .short('k')
+                        .long("kind")
+                        .value_parser(SetKind::ALL.map(|kind| kind.as_str()))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("rpe")
+                        .help("Rating of perceived exertion, 6 to 10 in steps of 0.5")
+                        .long("rpe")
+                        .value_parser(clap::value_parser!(f32))
+                        .conflicts_with("rir")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("rir")
+                        .help("Reps in reserve, 0 to 4")
+                        .long("rir")
+                        .value_parser(clap::value_parser!(u32))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("clear")
+                        .help("Measurements to remove from the set, comma separated")
+                        .long("clear")
+                        .value_parser(Metric::ALL.map(|metric| metric.as_str()))
+                        .value_delimiter(',')
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("clear-effort")
+                        .help("Remove the effort rating from the set")
+                        .long("clear-effort")
+                        .conflicts_with_all(["rpe", "rir"])
+                        .action(ArgAction::SetTrue),
+                ),
+            Command::new("delete-set")
+                .about("Deletes a set of the current workout session")
+                .arg(
+                    Arg::new("index")
+                        .help("The number of the set, as shown by display-workout")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(true)
+                        .index(1),
+                ),
+            Command::new("effort-trend")
+                .about("Shows the average RPE of an exercise per workout session")
+                .arg(
+                    Arg::new("exercise")
+                        .help("The exercise [default: the chosen exercise]")
+                        .index(1),
+                ),
+            Command::new("undo-last-set")
+                .about("Deletes the last set added to the current workout session"),
+        ])
+}
+
+/// Options for the values of a set beyond reps and weight, shared by add-set and edit-set.
+fn metric_args() -> [Arg; 4] {
+    [
+        Arg::new("duration")
+            .help("How long the set took, e.g. 90s, 1m30s or 1:30")
+            .long("duration")
+            .value_parser(units::parse_duration)
+            .action(ArgAction::Set),
+        Arg::new("distance")
+            .help("The distance covered, e.g. 400m, 2km or 1.5mi")
+            .long("distance")
+            .value_parser(units::parse_distance)
+            .action(ArgAction::Set),
+        Arg::new("calories")
+            .help("The calories burned")
+            .long("calories")
+            .value_parser(clap::value_parser!(u32))
+            .action(ArgAction::Set),
+        Arg::new("heart-rate")
+            .help("The average heart rate in beats per minute")
+            .long("heart-rate")
+            .value_parser(clap::value_parser!(u32))
+            .action(ArgAction::Set),
+    ]
 }
 
 fn main() {
     let binding = cli();
     let matches = binding.get_matches();
 
+    if let Err(e) = open_storage(&matches).and_then(|storage| run(storage.as_ref(), &matches)) {
+        match hint(&e) {
+            Some(hint) => eprintln!("error: {} — {}", e, hint),
+            None => eprintln!("error: {}", e),
+        }
+        process::exit(exit_code(&e));
+    }
+}
+
+/// What the user can do about an error, phrased as the next command to run.
+fn hint(error: &ResultError) -> Option<&'static str> {
+    match error {
+        ResultError::NoCurrentUser => {
+            Some("run `create-profile <name>` or `choose-profile <name>`")
+        }
+        ResultError::UserNotFound(_) => Some("run `list-profiles` to see existing profiles"),
+        ResultError::UserAlreadyExists(_) => Some("run `choose-profile <name>` to switch to it"),
+        ResultError::NoChosenSession => Some("run `start-workout` or `choose-workout <name>`"),
+        ResultError::SessionNotFound(_) => Some("run `list-workouts` to see existing sessions"),
+        ResultError::NoChosenExercise => Some("run `choose-exercise <name>`"),
+        ResultError::SetNotFound(_) => Some("run `display-workout` to see set numbers"),
+        ResultError::NoSets => Some("add one with `add-set <reps> <weight>`"),
+        ResultError::ExerciseNotFound(_) => {
+            Some("run `list-exercises` or create it with `create-exercise`")
+        }
+        ResultError::ExerciseInUse { .. } => {
+            Some("pass --archive to hide it from new sets and keep the history")
+        }
+        ResultError::Ambiguous { lookup, .. } => Some(match lookup {
+            Lookup::Session => "use a more specific title or date, or the session's id",
+            Lookup::Exercise | Lookup::Profile => "use a more specific name",
+        }),
+        ResultError::BackupNotFound(_) => Some("backups are numbered from 1, newest first"),
+        ResultError::NoDataDir => Some("set GYM_TRACKER_HOME or pass --data-dir <dir>"),
+        ResultError::UnsupportedSchemaVersion(_) => Some("upgrade gym_tracker to read this data"),
+        ResultError::LockTimeout(_) => Some("wait for the other command to finish and retry"),
+        _ => None,
+    }
+}
+
+/// Every error kind exits with its own code so scripts can tell them apart.
+/// Codes 1 and 2 are left to panics and clap's usage errors; storage failures
+/// reuse the matching BSD `sysexits.h` values.
+fn exit_code(error: &ResultError) -> i32 {
+    match error {
+        ResultError::UserNotFound(_) => 10,
+        ResultError::UserAlreadyExists(_) => 11,
+        ResultError::NoCurrentUser => 12,
+        ResultError::SessionNotFound(_) => 13,
+        ResultError::NoChosenSession => 14,
+        ResultError::ExerciseNotFound(_) => 15,
+        ResultError::NoChosenExercise => 16,
+        ResultError::Conflict(_) => 17,
+        ResultError::Unsupported(_) => 18,
+        ResultError::BackupNotFound(_) => 19,
+        ResultError::Ambiguous { .. } => 20,
+        ResultError::SetNotFound(_) => 21,
+        ResultError::NoSets => 22,
+        ResultError::InvalidInput(_) => 23,
+        ResultError::ExerciseInUse { .. } => 24,
+        ResultError::InvalidPath(_) => 64,
+        ResultError::InvalidDocument(_) => 65,
+        ResultError::FileNotFound(_) => 66,
+        ResultError::FileError(_) => 74,
+        ResultError::LockTimeout(_) => 75,
+        ResultError::UnsupportedSchemaVersion(_) => 76,
+        ResultError::NoDataDir => 78,
+    }
+}
+
+fn data_dir(matches: &ArgMatches) -> Result<PathBuf> {
+    storage::data_dir(matches.get_one::<PathBuf>("data-dir").map(PathBuf::as_path))
+}
+
+fn open_storage(matches: &ArgMatches) -> Result<Box<dyn Storage>> {
+    let dir = data_dir(matches)?;
+    match matches.get_one::<String>("storage").map(String::as_str) {
+        Some("sqlite") => Ok(Box::new(SqliteStorage::open_in_dir(&dir)?)),
+        _ => Ok(Box::new(JsonStorage::in_dir(&dir))),
+    }
+}
+
+fn run(storage: &dyn Storage, matches: &ArgMatches) -> Result<()> {
     match matches.subcommand() {
-        Some(("start-workout", _)) => workout_session::start(),
-        Some(("choose-workout", sub_m)) => {
-            let name = sub_m.get_one::<String>("name");
-            workout_session::choose(name.unwrap().to_string());
+        Some(("start-workout", sub_m)) => start_workout(storage, sub_m),
+        Some(("choose-workout", sub_m)) => choose_workout(storage, sub_m),
+        Some(("current-workout", sub_m)) => current_workout(storage, sub_m),
+        Some(("list-workouts", sub_m)) => list_workouts(storage, sub_m),
+        Some(("finish-workout", sub_m)) => finish_workout(storage, sub_m),
+        Some(("delete-workout", sub_m)) => delete_workout(storage, sub_m),
+        Some(("display-workout", sub_m)) => display_workout(storage, sub_m),
+        Some(("create-profile", sub_m)) => create_profile(storage, sub_m),
+        Some(("list-profiles", sub_m)) => list_profiles(storage, sub_m),
+        Some(("delete-profile", sub_m)) => delete_profile(storage, sub_m),
+        Some(("choose-profile", sub_m)) => choose_profile(storage, sub_m),
+        Some(("current-profile", sub_m)) => current_profile(storage, sub_m),
+        Some(("set-unit", sub_m)) => set_unit(storage, sub_m),
+        Some(("log-bodyweight", sub_m)) => log_bodyweight(storage, sub_m),
+        Some(("create-exercise", sub_m)) => create_exercise(storage, sub_m),
+        Some(("list-exercises", sub_m)) => list_exercises(storage, sub_m),
+        Some(("show-exercise", sub_m)) => show_exercise(storage, sub_m),
+        Some(("delete-exercise", sub_m)) => delete_exercise(storage, sub_m),
+        Some(("rename-exercise", sub_m)) => rename_exercise(storage, sub_m),
+        Some(("edit-exercise", sub_m)) => edit_exercise(storage, sub_m),
+        Some(("unarchive-exercise", sub_m)) => unarchive_exercise(storage, sub_m),
+        Some(("choose-exercise", sub_m)) => choose_exercise(storage, sub_m),
+        Some(("current-exercise", sub_m)) => current_exercise(storage, sub_m),
+        Some(("add-set", sub_m)) => add_set(storage, sub_m),
+        Some(("edit-set", sub_m)) => edit_set(storage, sub_m),
+        Some(("delete-set", sub_m)) => delete_set(storage, sub_m),
+        Some(("undo-last-set", sub_m)) => undo_last_set(storage, sub_m),
+        Some(("effort-trend", sub_m)) => effort_trend(storage, sub_m),
+        Some(("check", sub_m)) => check(storage, sub_m),
+        Some(("restore-backup", sub_m)) => restore_backup(storage, sub_m),
+        Some(("import-json", sub_m)) => import_json(storage, sub_m),
+        _ => Ok(()),
+    }
+}
+
+fn start_workout(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let title = args.get_one::<String>("title").cloned();
+    let workout_session = workout_session::start(storage, title)?;
+    println!("Starting workout session {}", workout_session);
+    Ok(())
+}
+
+fn choose_workout(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let workout_session = workout_session::choose(storage, name.to_string())?;
+    println!("Chose workout session: {}", workout_session);
+    Ok(())
+}
+
+fn current_workout(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let workout_session = workout_session::get_current_session(storage)?;
+    println!("Current workout session: {}", workout_session);
+    Ok(())
+}
+
+fn list_workouts(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    for workout in workout_session::list(storage)? {
+        println!("{}", workout);
+    }
+    Ok(())
+}
+
+fn delete_workout(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let workout_session = workout_session::delete(storage)?;
+    println!("Deleted workout session: {}", workout_session);
+    Ok(())
+}
+
+fn display_workout(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let user = user_profile::get_current_user(storage)?;
+    let current_session = workout_session::get_current_session(storage)?;
+    let unit = user.get_weight_unit();
+    let loads = Loads::for_session(&user, &current_session);
+    println!("Workout session: {}", current_session);
+    match current_session.get_ended_at() {
+        Some(ended_at) => println!(
+            "Finished: {} ({})",
+            local_time(ended_at),
+            format_duration(ended_at - current_session.get_started_at())
+        ),
+        None => println!("In progress"),
+    }
+    println!(
+        "Volume: {} (excluding warm-ups)",
+        Weight::kg(analytics::volume(current_session.get_sets(), &loads)).convert(unit)
+    );
+    if let Some(bodyweight) = loads.get_bodyweight() {
+        println!("Bodyweight: {}", Weight::kg(bodyweight).convert(unit));
+    }
+    println!();
+
+    let rest_times = current_session.get_rest_times();
+    for (index, (set, rest)) in current_session
+        .get_sets()
+        .iter()
+        .zip(rest_times)
+        .enumerate()
+    {
+        println!("Set {}: {}", index + 1, set.get_exercise());
+        if let Some(reps) = set.get_reps() {
+            println!("Reps: {}", reps);
         }
-        Some(("current-workout", _)) => {
-            let workout_session = workout_session::get_current_session();
+        if let (Some(weight), Some(entered)) =
+            (set.get_weight_in(unit), set.get_weight_in(set.get_unit()))
+        {
+            if set.get_unit() == unit {
+                println!("Weight: {}", weight);
+            } else {
+                println!("Weight: {} (entered as {})", weight, entered);
+            }
+        }
+        if let Some(duration) = set.get_duration() {
             println!(
-                "Current workout session: {}",
-                workout_session.get_name().to_string()
+                "Duration: {}",
+                format_duration(Duration::seconds(duration.into()))
             );
         }
-        Some(("list-workouts", _)) => {
-            workout_session::list();
-        }
-        Some(("delete-workout", _)) => {
-            workout_session::delete();
+        if let Some(distance) = set.get_distance() {
+            println!("Distance: {}", units::format_distance(distance));
         }
-        Some(("display-workout", _)) => {
-            workout_session::display();
+        if let Some(calories) = set.get_calories() {
+            println!("Calories: {} kcal", calories);
         }
-        Some(("create-profile", sub_m)) => {
-            let name = sub_m.get_one::<String>("name");
-            user_profile::create_profile(name.unwrap().to_string()).unwrap();
-            user_profile::choose_profile(name.unwrap().to_string()).unwrap();
+        if let Some(heart_rate) = set.get_heart_rate() {
+            println!("Heart rate: {} bpm", heart_rate);
         }
-        Some(("list-profiles", _)) => {
-            let users = user_profile::read_profiles().expect("Unable to read user profiles");
-            for user in users.list() {
-                println!("{}", user.get_name());
+        let loading = loads.loading(set.get_exercise());
+        if loading != Loading::External {
+            match loads.load(set) {
+                Some(load) => println!("Load: {} ({})", Weight::kg(load).convert(unit), loading),
+                None => println!("Load: unknown, log your bodyweight first ({})", loading),
             }
         }
-        Some(("delete-profile", sub_m)) => {
-            let name = sub_m.get_one::<String>("name");
-            user_profile::delete_profile(name.unwrap().to_string()).unwrap();
-            println!("Deleted profile: {}", name.unwrap());
-            user_profile::choose_profile("default".to_string()).unwrap();
-        }
-        Some(("choose-profile", sub_m)) => {
-            let name = sub_m.get_one::<String>("name");
-            user_profile::choose_profile(name.unwrap().to_string()).unwrap();
-            println!("Chosen profile {}", name.unwrap());
-        }
-        Some(("current-profile", _)) => {
-            let user = user_profile::read_current_user().unwrap();
-            println!("Current user: {}", user);
-        }
-        Some(("create-exercise", sub_m)) => {
-            let name = sub_m.get_one::<String>("name").unwrap();
-            let description = match sub_m.get_one::<String>("description") {
-                Some(description) => Some(description.to_string()),
-                None => None,
-            };
-            let muscle_groups = match sub_m.get_many::<String>("musclegroups") {
-                Some(muscle_groups) => muscle_groups.map(|s| s.to_string()).collect(),
-                None => vec![],
-            };
-
-            let equipment = sub_m.get_one::<String>("equipment").unwrap();
-
-            exercise::create_exercise(
-                name.to_string(),
-                description,
-                muscle_groups,
-                equipment.to_string(),
-            )
-            .unwrap();
+        println!("Kind: {}", set.get_kind());
+        if let Some(effort) = set.get_effort() {
+            println!("Effort: {}", effort);
         }
-        Some(("list-exercises", _)) => {
-            let user_profile = user_profile::get_current_user().unwrap();
-            for exercise in user_profile.get_exercises() {
-                println!("{}", exercise.get_name());
-            }
+        if let Some(performed_at) = set.get_performed_at() {
+            println!("Performed at: {}", local_time(performed_at));
         }
-        Some(("delete-exercise", sub_m)) => {
-            let name = sub_m.get_one::<String>("name").unwrap();
-            exercise::delete_exercise(name).unwrap();
-        }
-        Some(("choose-exercise", sub_m)) => {
-            let name = sub_m.get_one::<String>("name").unwrap();
-            exercise::choose_exercise(name.to_string()).unwrap();
-        }
-        Some(("current-exercise", _)) => {
-            let profile = user_profile::get_current_user().unwrap();
-            let exercise = profile.get_chosen_exercise().unwrap();
-            println!("Current exercise: {}", exercise);
-        }
-        Some(("add-set", sub_m)) => {
-            let reps = sub_m.get_one::<String>("reps").unwrap();
-            let weight = sub_m.get_one::<String>("weight").unwrap();
-
-            let current_user = user_profile::get_current_user().unwrap();
-            let chosen_exercise = current_user.get_chosen_exercise().unwrap();
-            set::create_set(
-                chosen_exercise.to_owned(),
-                reps.parse::<u32>().unwrap(),
-                weight.parse::<f32>().unwrap(),
-                false,
+        if let Some(rest) = rest {
+            println!("Rest: {}", format_duration(rest));
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn finish_workout(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let workout_session = workout_session::finish(storage)?;
+    let duration = workout_session.get_duration().unwrap_or_default();
+    println!(
+        "Finished workout session {} after {}",
+        workout_session,
+        format_duration(duration)
+    );
+    Ok(())
+}
+
+fn local_time(at: DateTime<Utc>) -> String {
+    at.with_timezone(&Local)
+        .format("%m-%d-%Y %H:%M:%S")
+        .to_string()
+}
+
+/// Formats a duration as e.g. `1h 05m`, `3m 20s` or `45s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn create_profile(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    user_profile::create_profile(storage, name.to_string())?;
+    user_profile::choose_profile(storage, name.to_string())?;
+    Ok(())
+}
+
+fn list_profiles(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let users = user_profile::read_profiles(storage)?;
+    for user in users.list() {
+        println!("{}", user.get_name());
+    }
+    Ok(())
+}
+
+fn delete_profile(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    user_profile::delete_profile(storage, name.to_string())?;
+    println!("Deleted profile: {}", name);
+    user_profile::choose_profile(storage, "default".to_string())?;
+    Ok(())
+}
+
+fn choose_profile(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let chosen = user_profile::choose_profile(storage, name.to_string())?;
+    println!("Chosen profile {}", chosen);
+    Ok(())
+}
+
+fn current_profile(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let user = user_profile::read_current_user(storage)?;
+    println!("Current user: {}", user);
+    Ok(())
+}
+
+fn set_unit(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let unit = args.get_one::<String>("unit").unwrap();
+    let unit = WeightUnit::from_name(unit).unwrap_or_default();
+    user_profile::set_weight_unit(storage, unit)?;
+    println!("Showing weights in {}", unit);
+    Ok(())
+}
+
+fn log_bodyweight(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let unit = weight_unit(storage)?;
+    let weight = args.get_one::<WeightInput>("weight").unwrap();
+    let entry = bodyweight::log_bodyweight(storage, weight.with_default_unit(unit))?;
+    println!(
+        "Logged bodyweight {}",
+        Weight::kg(entry.get_weight()).convert(unit)
+    );
+    Ok(())
+}
+
+/// The current profile's preferred weight unit.
+fn weight_unit(storage: &dyn Storage) -> Result<WeightUnit> {
+    Ok(user_profile::get_current_user(storage)?.get_weight_unit())
+}
+
+fn create_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let description = args.get_one::<String>("description").cloned();
+    let primary_muscles = match args.get_many::<MuscleGroup>("musclegroups") {
+        Some(muscle_groups) => muscle_groups.copied().collect(),
+        None => vec![],
+    };
+    let secondary_muscles = match args.get_many::<MuscleGroup>("secondary") {
+        Some(muscle_groups) => muscle_groups.copied().collect(),
+        None => vec![],
+    };
+
+    let equipment = *args.get_one::<Equipment>("equipment").unwrap();
+    let loading = args
+        .get_one::<String>("loading")
+        .and_then(|loading| Loading::from_name(loading))
+        .unwrap_or_default();
+    let metrics = args
+        .get_many::<String>("metrics")
+        .map(|metrics| {
+            metrics
+                .filter_map(|metric| Metric::from_name(metric))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let aliases = match args.get_many::<String>("aliases") {
+        Some(aliases) => aliases.map(|s| s.to_string()).collect(),
+        None => vec![],
+    };
+
+    let exercise = Exercise::new(name.to_string(), description, primary_muscles, equipment)
+        .with_secondary_muscles(secondary_muscles)
+        .with_loading(loading)
+        .with_metrics(metrics)
+        .with_aliases(aliases);
+    exercise::create_exercise(storage, exercise)
+}
+
+fn list_exercises(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let user_profile = user_profile::get_current_user(storage)?;
+    let exercises = if args.get_flag("custom") {
+        user_profile.get_exercises().iter().collect()
+    } else {
+        user_profile.all_exercises()
+    };
+    let filter = ExerciseFilter {
+        muscle: args.get_one::<MuscleGroup>("muscle").copied(),
+        equipment: args.get_one::<Equipment>("equipment").copied(),
+        search: args.get_one::<String>("search").cloned(),
+    };
+    for exercise in exercises
+        .into_iter()
+        .filter(|exercise| filter.matches(exercise))
+    {
+        if exercise.is_archived() {
+            println!("{} (archived)", exercise.get_name());
+        } else {
+            println!("{}", exercise.get_name());
+        }
+    }
+    Ok(())
+}
+
+fn edit_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let muscles = |id| {
+        args.get_many::<MuscleGroup>(id)
+            .map(|muscles| muscles.copied().collect())
+    };
+    let edit = ExerciseEdit {
+        description: args.get_one::<String>("description").cloned(),
+        primary_muscles: muscles("musclegroups"),
+        secondary_muscles: if args.get_flag("clear-secondary") {
+            Some(Vec::new())
+        } else {
+            muscles("secondary")
+        },
+        equipment: args.get_one::<Equipment>("equipment").copied(),
+        loading: args
+            .get_one::<String>("loading")
+            .and_then(|loading| Loading::from_name(loading)),
+    };
+    let loading = user_profile::get_current_user(storage)?
+        .find_exercise(name)
+        .map(|exercise| exercise.get_loading());
+    let exercise = exercise::edit_exercise(storage, name, edit)?;
+    println!("Edited exercise {}", exercise.get_name());
+    if loading.is_some_and(|loading| loading != exercise.get_loading()) {
+        let sets = user_profile::get_current_user(storage)?.count_sets_of(exercise.get_name());
+        if sets > 0 {
+            eprintln!(
+                "warning: the volume of the {} set(s) already logged now counts the load as {}",
+                sets,
+                exercise.get_loading().as_str()
             );
         }
-        _ => {}
     }
+    Ok(())
+}
+
+fn show_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let exercise = exercise::get_exercise(storage, name.to_string())?;
+    let user_profile = user_profile::get_current_user(storage)?;
+
+    print!("{}", exercise.get_name());
+    if exercise.is_archived() {
+        print!(" (archived)");
+    }
+    println!();
+    if !exercise.get_description().is_empty() {
+        println!("{}", exercise.get_description());
+    }
+    if !exercise.get_aliases().is_empty() {
+        println!("Also called: {}", join(exercise.get_aliases()));
+    }
+    println!("Primary muscles: {}", join(exercise.get_primary_muscles()));
+    if !exercise.get_secondary_muscles().is_empty() {
+        println!(
+            "Secondary muscles: {}",
+            join(exercise.get_secondary_muscles())
+        );
+    }
+    println!("Equipment: {}", exercise.get_equipment());
+    println!("Loading: {}", exercise.get_loading());
+    println!("Metrics: {}", join(exercise.get_metrics()));
+
+    let stats = analytics::exercise_stats(&user_profile, exercise.get_name());
+    match stats.last_performed {
+        Some(last_performed) => {
+            println!("Sessions: {}, sets: {}", stats.sessions, stats.sets);
+            println!("Last performed: {}", local_time(last_performed));
+        }
+        None => println!("Not performed yet"),
+    }
+    if let Some(set) = stats.best_set {
+        println!(
+            "Best set: {}",
+            describe_set(set, user_profile.get_weight_unit())
+        );
+    }
+    Ok(())
+}
+
+fn delete_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    if args.get_flag("archive") {
+        exercise::archive_exercise(storage, name)?;
+        println!("Archived exercise {}", name);
+    } else {
+        exercise::delete_exercise(storage, name)?;
+        println!("Deleted exercise {}", name);
+    }
+    Ok(())
+}
+
+fn rename_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let old = args.get_one::<String>("old").unwrap();
+    let new = args.get_one::<String>("new").unwrap();
+    let sets = exercise::rename_exercise(storage, old, new)?;
+    println!("Renamed {} to {} in {} set(s)", old, new, sets);
+    Ok(())
+}
+
+fn unarchive_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    exercise::unarchive_exercise(storage, name)?;
+    println!("Unarchived exercise {}", name);
+    Ok(())
+}
+
+fn choose_exercise(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let chosen = exercise::choose_exercise(storage, name.to_string())?;
+    if &chosen != name {
+        println!("Chose {}", chosen);
+    }
+    Ok(())
+}
+
+fn current_exercise(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let exercise = exercise::get_chosen_exercise(storage)?;
+    println!("Current exercise: {}", exercise);
+    Ok(())
+}
+
+fn add_set(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let weight = args
+        .get_one::<WeightInput>("weight")
+        .or(args.get_one::<WeightInput>("weight-option"));
+    let values = set_values(storage, args, weight)?;
+
+    let kind = if args.get_flag("dropset") {
+        SetKind::Dropset
+    } else {
+        set_kind(args).unwrap_or_default()
+    };
+
+    let chosen_exercise = exercise::get_chosen_exercise(storage)?;
+    set::create_set(storage, chosen_exercise, values, kind, effort(args)?)?;
+    Ok(())
+}
+
+fn edit_set(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let index = *args.get_one::<usize>("index").unwrap();
+    let edit = SetEdit {
+        exercise: args.get_one::<String>("exercise").cloned(),
+        values: set_values(storage, args, args.get_one::<WeightInput>("weight"))?,
+        clear: args
+            .get_many::<String>("clear")
+            .map(|metrics| {
+                metrics
+                    .filter_map(|metric| Metric::from_name(metric))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        kind: set_kind(args),
+        effort: effort(args)?,
+        clear_effort: args.get_flag("clear-effort"),
+    };
+    let set = set::edit_set(storage, index, edit)?;
+    println!(
+        "Edited set {}: {}",
+        index,
+        describe_set(&set, weight_unit(storage)?)
+    );
+    Ok(())
+}
+
+fn delete_set(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let index = *args.get_one::<usize>("index").unwrap();
+    let set = set::delete_set(storage, index)?;
+    println!(
+        "Deleted set {}: {}",
+        index,
+        describe_set(&set, weight_unit(storage)?)
+    );
+    Ok(())
+}
+
+fn undo_last_set(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let set = set::undo_last_set(storage)?;
+    println!("Removed set: {}", describe_set(&set, weight_unit(storage)?));
+    Ok(())
+}
+
+/// The reps, `weight` and the metric options; a weight without a unit is taken
+/// to be in the profile's unit.
+fn set_values(
+    storage: &dyn Storage,
+    args: &ArgMatches,
+    weight: Option<&WeightInput>,
+) -> Result<SetValues> {
+    Ok(SetValues {
+        reps: args.get_one::<u32>("reps").copied(),
+        weight: match weight {
+            Some(weight) => Some(weight.with_default_unit(weight_unit(storage)?)),
+            None => None,
+        },
+        duration: args.get_one::<u32>("duration").copied(),
+        distance: args.get_one::<f32>("distance").copied(),
+        calories: args.get_one::<u32>("calories").copied(),
+        heart_rate: args.get_one::<u32>("heart-rate").copied(),
+    })
+}
+
+/// The `--kind` option; clap has already checked it names a `SetKind`.
+fn set_kind(args: &ArgMatches) -> Option<SetKind> {
+    args.get_one::<String>("kind")
+        .and_then(|kind| SetKind::from_name(kind))
+}
+
+/// The `--rpe` or `--rir` option, checked against the allowed range.
+fn effort(args: &ArgMatches) -> Result<Option<Effort>> {
+    if let Some(rpe) = args.get_one::<f32>("rpe") {
+        return Effort::rpe(*rpe).map(Some);
+    }
+    if let Some(rir) = args.get_one::<u32>("rir") {
+        return Effort::rir(*rir).map(Some);
+    }
+    Ok(None)
+}
+
+fn effort_trend(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let exercise = match args.get_one::<String>("exercise") {
+        Some(exercise) => exercise::get_exercise(storage, exercise.to_string())?
+            .get_name()
+            .to_string(),
+        None => exercise::get_chosen_exercise(storage)?,
+    };
+    let sessions = workout_session::list(storage)?;
+    let trend = analytics::effort_trend(&sessions, &exercise);
+    if trend.is_empty() {
+        println!("No rated sets of {}", exercise);
+    }
+    for point in trend {
+        println!(
+            "{}: RPE {:.1} over {} sets",
+            point.session, point.average_rpe, point.sets
+        );
+    }
+    Ok(())
+}
+
+/// A one-line summary such as `squat 5 x 100 kg` or `row 2 km, 8m 00s`.
+fn describe_set(set: &Set, unit: WeightUnit) -> String {
+    let mut parts = Vec::new();
+    match (set.get_reps(), set.get_weight_in(unit)) {
+        (Some(reps), Some(weight)) => parts.push(format!("{} x {}", reps, weight)),
+        (Some(reps), None) => parts.push(format!("{} reps", reps)),
+        (None, Some(weight)) => parts.push(weight.to_string()),
+        (None, None) => {}
+    }
+    if let Some(distance) = set.get_distance() {
+        parts.push(units::format_distance(distance));
+    }
+    if let Some(duration) = set.get_duration() {
+        parts.push(format_duration(Duration::seconds(duration.into())));
+    }
+    if let Some(calories) = set.get_calories() {
+        parts.push(format!("{} kcal", calories));
+    }
+    if let Some(heart_rate) = set.get_heart_rate() {
+        parts.push(format!("{} bpm", heart_rate));
+    }
+    format!("{} {}", set.get_exercise(), parts.join(", "))
+}
+
+/// Comma-separated display of `values`.
+fn join<T: std::fmt::Display>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn check(storage: &dyn Storage, _args: &ArgMatches) -> Result<()> {
+    let problems = integrity::check(storage)?;
+    if problems.is_empty() {
+        println!("No problems found");
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    Ok(())
+}
+
+fn restore_backup(storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let generation = *args.get_one::<usize>("generation").unwrap();
+    storage.restore_backup(generation)?;
+    // the replaced data becomes backup 1 and every older backup moves down one
+    if generation < BACKUP_COUNT {
+        println!(
+            "Restored backup {}, which is now backup {}; the replaced data is backup 1 \
+             and the other backups moved down one",
+            generation,
+            generation + 1
+        );
+    } else {
+        println!(
+            "Restored backup {}; the replaced data is backup 1 and the other backups \
+             moved down one, so the restored one is no longer kept as a backup",
+            generation
+        );
+    }
+    Ok(())
+}
+
+fn import_json(_storage: &dyn Storage, args: &ArgMatches) -> Result<()> {
+    let file = Path::new(args.get_one::<String>("file").unwrap());
+    // a JSON storage reads a missing file as no profiles, which would hide a typo
+    if !file.is_file() {
+        return Err(ResultError::FileNotFound(file.to_path_buf()));
+    }
+    let current_user_file = file.with_file_name("current_user.json");
+    let source = JsonStorage::new(file, current_user_file);
+    let database = data_dir(args)?.join(DATABASE_FILE_NAME);
+    let destination = SqliteStorage::open(&database)?;
+
+    let imported = storage::import(&source, &destination)?;
+    println!("Imported {} profiles into {}", imported, database.display());
+    Ok(())
 }
@@ -0,0 +1,500 @@
+//! Upgrades JSON documents written by older versions of the tracker.
+//!
+//! Every document carries a top-level `schema_version`. Files written before it existed
+//! count as version 1. When a stored type changes, bump `SCHEMA_VERSION` and append a
+//! step to `MIGRATIONS` that rewrites the previous version's JSON into the new shape.
+
+use crate::errors::Result;
+use crate::errors::ResultError;
+use crate::exercise::{Equipment, MuscleGroup};
+use chrono::{Duration, NaiveDate, NaiveTime};
+use serde_json::{json, Map, Value};
+
+pub const SCHEMA_VERSION: u64 = 12;
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// `MIGRATIONS[i]` upgrades a document from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[Migration] = &[
+    current_user_pointer,
+    session_ids,
+    timestamps,
+    set_kinds,
+    set_effort,
+    weight_units,
+    bodyweight,
+    metrics,
+    archived_exercises,
+    exercise_aliases,
+    structured_exercises,
+];
+
+/// v1 -> v2: `current_user.json` held a full `UserProfile` snapshot; keep only its name.
+fn current_user_pointer(document: &mut Map<String, Value>) -> Result<()> {
+    if let Some(profile) = document.remove("UserProfile") {
+        let name = profile
+            .get("name")
+            .cloned()
+            .ok_or(ResultError::InvalidDocument(
+                "current user has no name".to_string(),
+            ))?;
+        document.insert("CurrentUser".to_string(), json!({ "name": name }));
+    }
+    Ok(())
+}
+
+/// Calls `f` on every profile of a `Users` document; other documents are left alone.
+fn for_each_user(
+    document: &mut Map<String, Value>,
+    mut f: impl FnMut(&mut Map<String, Value>) -> Result<()>,
+) -> Result<()> {
+    let users = match document
+        .get_mut("Users")
+        .and_then(|users| users.get_mut("users"))
+    {
+        Some(Value::Array(users)) => users,
+        _ => return Ok(()),
+    };
+    for user in users {
+        let user = user.as_object_mut().ok_or(ResultError::InvalidDocument(
+            "expected a user profile".to_string(),
+        ))?;
+        f(user)?;
+    }
+    Ok(())
+}
+
+/// Calls `f` on every set of every profile of a `Users` document.
+fn for_each_set(
+    document: &mut Map<String, Value>,
+    mut f: impl FnMut(&mut Map<String, Value>),
+) -> Result<()> {
+    for_each_user(document, |user| {
+        let Some(Value::Array(workouts)) = user.get_mut("workouts") else {
+            return Ok(());
+        };
+        for workout in workouts {
+            if let Some(Value::Array(sets)) = workout.get_mut("sets") {
+                sets.iter_mut()
+                    .filter_map(Value::as_object_mut)
+                    .for_each(&mut f);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// v2 -> v3: sessions were identified by their date, so two on one day collided.
+/// Number them in order and point `chosen_workout_session` at the first one with
+/// the chosen date, which is the one the old lookup picked.
+fn session_ids(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_user(document, |user| {
+        let chosen_name = user.get("chosen_workout_session").cloned();
+        let mut chosen = None;
+        let mut count = 0;
+        if let Some(Value::Array(workouts)) = user.get_mut("workouts") {
+            for (index, workout) in workouts.iter_mut().enumerate() {
+                let id = index as u64 + 1;
+                let workout = workout.as_object_mut().ok_or(ResultError::InvalidDocument(
+                    "expected a workout".to_string(),
+                ))?;
+                if chosen.is_none() && workout.get("name") == chosen_name.as_ref() {
+                    chosen = Some(id);
+                }
+                workout.insert("id".to_string(), id.into());
+                workout.insert("title".to_string(), Value::Null);
+                count = id;
+            }
+        }
+        user.insert("chosen_workout_session".to_string(), chosen.into());
+        user.insert("next_session_id".to_string(), (count + 1).into());
+        Ok(())
+    })
+}
+
+/// v3 -> v4: sessions only had a UTC date in `name`. Start them at noon UTC on that
+/// date, which lands on the same calendar day in nearly every time zone. Old sets
+/// have no time at all and stay that way.
+fn timestamps(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_user(document, |user| {
+        let Some(Value::Array(workouts)) = user.get_mut("workouts") else {
+            return Ok(());
+        };
+        for workout in workouts {
+            let workout = workout.as_object_mut().ok_or(ResultError::InvalidDocument(
+                "expected a workout".to_string(),
+            ))?;
+            let name = workout.remove("name").unwrap_or(Value::Null);
+            let date = name
+                .as_str()
+                .and_then(|name| NaiveDate::parse_from_str(name, "%m-%d-%Y").ok())
+                .ok_or(ResultError::InvalidDocument(format!(
+                    "invalid workout date: {}",
+                    name
+                )))?;
+            let started_at = date.and_time(NaiveTime::MIN) + Duration::hours(12);
+            workout.insert(
+                "started_at".to_string(),
+                json!(started_at.and_utc().to_rfc3339()),
+            );
+            workout.insert("ended_at".to_string(), Value::Null);
+
+            if let Some(Value::Array(sets)) = workout.get_mut("sets") {
+                for set in sets.iter_mut().filter_map(Value::as_object_mut) {
+                    set.insert("performed_at".to_string(), Value::Null);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// v4 -> v5: the `is_dropset` flag became one of several set kinds.
+fn set_kinds(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_set(document, |set| {
+        let is_dropset = set.remove("is_dropset").and_then(|x| x.as_bool());
+        let kind = if is_dropset == Some(true) {
+            "dropset"
+        } else {
+            "normal"
+        };
+        set.insert("kind".to_string(), kind.into());
+    })
+}
+
+/// v5 -> v6: sets gained an optional RPE/RIR rating.
+fn set_effort(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_set(document, |set| {
+        set.insert("effort".to_string(), Value::Null);
+    })
+}
+
+/// v6 -> v7: weights had no unit. Everyone used kilograms so far, so record that
+/// on each set and make it the profile's display unit.
+fn weight_units(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_set(document, |set| {
+        set.insert("unit".to_string(), "kg".into());
+    })?;
+    for_each_user(document, |user| {
+        user.insert("weight_unit".to_string(), "kg".into());
+        Ok(())
+    })
+}
+
+/// v7 -> v8: exercises gained a loading type and profiles a bodyweight log.
+fn bodyweight(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_user(document, |user| {
+        if let Some(Value::Array(exercises)) = user.get_mut("created_exercises") {
+            for exercise in exercises.iter_mut().filter_map(Value::as_object_mut) {
+                exercise.insert("loading".to_string(), "external".into());
+            }
+        }
+        user.insert("bodyweights".to_string(), json!([]));
+        Ok(())
+    })
+}
+
+/// v8 -> v9: exercises declare the metrics they track, and sets gained the values
+/// for the metrics beyond reps and weight. Everything so far was reps and weight.
+fn metrics(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_set(document, |set| {
+        for field in ["duration", "distance", "calories", "heart_rate"] {
+            set.insert(field.to_string(), Value::Null);
+        }
+    })?;
+    for_each_user(document, |user| {
+        if let Some(Value::Array(exercises)) = user.get_mut("created_exercises") {
+            for exercise in exercises.iter_mut().filter_map(Value::as_object_mut) {
+                exercise.insert("metrics".to_string(), json!(["reps", "weight"]));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// v9 -> v10: exercises can be archived instead of deleted.
+fn archived_exercises(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_user(document, |user| {
+        if let Some(Value::Array(exercises)) = user.get_mut("created_exercises") {
+            for exercise in exercises.iter_mut().filter_map(Value::as_object_mut) {
+                exercise.insert("archived".to_string(), false.into());
+            }
+        }
+        Ok(())
+    })
+}
+
+/// v10 -> v11: exercises gained aliases.
+fn exercise_aliases(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_user(document, |user| {
+        if let Some(Value::Array(exercises)) = user.get_mut("created_exercises") {
+            for exercise in exercises.iter_mut().filter_map(Value::as_object_mut) {
+                exercise.insert("aliases".to_string(), json!([]));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// v11 -> v12: free-text muscle groups and equipment became `MuscleGroup` and
+/// `Equipment` values; see `structure_free_text`.
+fn structured_exercises(document: &mut Map<String, Value>) -> Result<()> {
+    for_each_user(document, |user| {
+        let Some(Value::Array(exercises)) = user.get_mut("created_exercises") else {
+            return Ok(());
+        };
+        for exercise in exercises.iter_mut().filter_map(Value::as_object_mut) {
+            let muscle_groups: Vec<String> = match exercise.remove("muscle_groups") {
+                Some(Value::Array(groups)) => groups
+                    .iter()
+                    .filter_map(|group| group.as_str().map(str::to_string))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let structured = structure_free_text(
+                exercise
+                    .get("description")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                &muscle_groups,
+                exercise
+                    .get("equipment")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+            );
+            let primary: Vec<&str> = structured
+                .primary_muscles
+                .iter()
+                .map(MuscleGroup::as_str)
+                .collect();
+            exercise.insert("primary_muscles".to_string(), json!(primary));
+            exercise.insert("secondary_muscles".to_string(), json!([]));
+            exercise.insert(
+                "equipment".to_string(),
+                structured.equipment.as_str().into(),
+            );
+            exercise.insert("description".to_string(), structured.description.into());
+        }
+        Ok(())
+    })
+}
+
+/// An exercise's muscle groups and equipment as typed before they were structured.
+pub(crate) struct StructuredFields {
+    /// Every recognized group; there was no notion of secondary muscles.
+    pub primary_muscles: Vec<MuscleGroup>,
+    /// `Equipment::Other` if the text didn't name known equipment.
+    pub equipment: Equipment,
+    /// The old description, followed by any text that couldn't be mapped so it
+    /// isn't lost.
+    pub description: String,
+}
+
+/// Maps free-text muscle groups and equipment onto the structured values, for both
+/// the JSON and the SQLite migrations.
+pub(crate) fn structure_free_text(
+    description: &str,
+    muscle_groups: &[String],
+    equipment: &str,
+) -> StructuredFields {
+    let mut primary_muscles = Vec::new();
+    let mut unrecognized = Vec::new();
+    for group in muscle_groups {
+        match MuscleGroup::from_name(group) {
+            Some(muscle) if !primary_muscles.contains(&muscle) => primary_muscles.push(muscle),
+            Some(_) => {}
+            None => unrecognized.push(group.as_str()),
+        }
+    }
+
+    let mut notes = Vec::new();
+    if !unrecognized.is_empty() {
+        notes.push(format!("Muscle groups: {}", unrecognized.join(", ")));
+    }
+    let structured_equipment = Equipment::from_name(equipment);
+    if structured_equipment.is_none() && !equipment.trim().is_empty() {
+        notes.push(format!("Equipment: {}", equipment.trim()));
+    }
+    let description = std::iter::once(description.trim())
+        .filter(|description| !description.is_empty())
+        .chain(notes.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    StructuredFields {
+        primary_muscles,
+        equipment: structured_equipment.unwrap_or_default(),
+        description,
+    }
+}
+
+/// Brings `document` up to `SCHEMA_VERSION` and strips the version field,
+/// leaving plain `FileContents` JSON.
+pub fn migrate(document: &mut Value) -> Result<()> {
+    let object = document
+        .as_object_mut()
+        .ok_or(ResultError::InvalidDocument(
+            "expected a JSON object".to_string(),
+        ))?;
+
+    let version = match object.remove(SCHEMA_VERSION_KEY) {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .ok_or(ResultError::InvalidDocument(format!(
+                "invalid {}: {}",
+                SCHEMA_VERSION_KEY, version
+            )))?,
+    };
+    if version == 0 {
+        return Err(ResultError::InvalidDocument(format!(
+            "invalid {}: 0",
+            SCHEMA_VERSION_KEY
+        )));
+    }
+    if version > SCHEMA_VERSION {
+        return Err(ResultError::UnsupportedSchemaVersion(version));
+    }
+
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(object)?;
+    }
+    Ok(())
+}
+
+/// Marks a freshly serialized document as being at the current `SCHEMA_VERSION`.
+pub fn stamp(document: &mut Value) -> Result<()> {
+    document
+        .as_object_mut()
+        .ok_or(ResultError::InvalidDocument(
+            "expected a JSON object".to_string(),
+        ))?
+        .insert(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exercise::Metric;
+    use crate::set::SetKind;
+    use crate::user_profile::{CurrentUser, UserProfile, Users};
+    use crate::utils::test_utils::{clear_path, TEST_FILE};
+    use crate::utils::{read_file, update_file, FileContents};
+    use std::fs;
+
+    const FIXTURES: &str = "test_files/fixtures";
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", FIXTURES, name)
+    }
+
+    #[test]
+    fn test_read_unversioned_document() {
+        let contents = read_file(&fixture("v1_users.json")).unwrap();
+        let FileContents::Users(users) = contents else {
+            panic!("expected users, got {:?}", contents);
+        };
+
+        let user = users.get_user(&"test".to_string()).unwrap();
+        let squat = &user.get_exercises()[0];
+        assert_eq!(squat.get_name(), "squat");
+        assert_eq!(squat.get_metrics(), &Metric::DEFAULT.to_vec());
+        assert_eq!(squat.get_primary_muscles(), &vec![MuscleGroup::Glutes]);
+        assert_eq!(squat.get_equipment(), Equipment::Barbell);
+        assert_eq!(squat.get_description(), "back squat\nMuscle groups: legs");
+        assert_eq!(user.get_workouts()[0].get_sets()[0].get_duration(), None);
+        let kinds: Vec<SetKind> = user.get_workouts()[0]
+            .get_sets()
+            .iter()
+            .map(|set| set.get_kind())
+            .collect();
+        assert_eq!(kinds, vec![SetKind::Normal, SetKind::Dropset]);
+    }
+
+    #[test]
+    fn test_structure_free_text() {
+        let fields = structure_free_text(
+            "",
+            &["Pecs".to_string(), "chest".to_string(), "arms".to_string()],
+            "cable machine",
+        );
+        assert_eq!(fields.primary_muscles, vec![MuscleGroup::Chest]);
+        assert_eq!(fields.equipment, Equipment::Other);
+        assert_eq!(
+            fields.description,
+            "Muscle groups: arms\nEquipment: cable machine"
+        );
+
+        let fields = structure_free_text("flat bench", &[], "Dumbbells");
+        assert_eq!(fields.primary_muscles, vec![]);
+        assert_eq!(fields.equipment, Equipment::Dumbbell);
+        assert_eq!(fields.description, "flat bench");
+    }
+
+    #[test]
+    fn test_migrate_session_ids() {
+        let contents = read_file(&fixture("v2_users_same_day.json")).unwrap();
+        let FileContents::Users(users) = contents else {
+            panic!("expected users, got {:?}", contents);
+        };
+
+        let user = users.get_user(&"test".to_string()).unwrap();
+        let ids: Vec<u32> = user.get_workouts().iter().map(|w| w.get_id()).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(user.get_chosen_workout_session(), Some(2));
+        assert_eq!(user.get_next_session_id(), 4);
+        assert_eq!(user.get_workout(2).unwrap().get_sets().len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_timestamps() {
+        let contents = read_file(&fixture("v1_users.json")).unwrap();
+        let FileContents::Users(users) = contents else {
+            panic!("expected users, got {:?}", contents);
+        };
+
+        let workout = &users.get_user(&"test".to_string()).unwrap().get_workouts()[0];
+        assert_eq!(
+            workout.get_started_at().to_rfc3339(),
+            "2024-01-15T12:00:00+00:00"
+        );
+        assert_eq!(workout.get_ended_at(), None);
+        assert_eq!(workout.get_sets()[0].get_performed_at(), None);
+    }
+
+    #[test]
+    fn test_migrate_current_user_snapshot() {
+        let contents = read_file(&fixture("v1_current_user.json")).unwrap();
+        assert_eq!(
+            contents,
+            FileContents::CurrentUser(CurrentUser::new("test".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_written_documents_are_versioned() {
+        let unique_path = TEST_FILE.to_string() + "_versioned";
+        let mut users = Users::new();
+        users.add_user(UserProfile::new("test".to_string()));
+        update_file(&unique_path, &FileContents::Users(users.clone())).unwrap();
+
+        let raw: Value = serde_json::from_str(&fs::read_to_string(&unique_path).unwrap()).unwrap();
+        assert_eq!(raw[SCHEMA_VERSION_KEY], SCHEMA_VERSION);
+        assert_eq!(read_file(&unique_path), Ok(FileContents::Users(users)));
+        clear_path(&unique_path);
+    }
+
+    #[test]
+    fn test_reject_newer_version() {
+        let mut document = serde_json::json!({
+            SCHEMA_VERSION_KEY: SCHEMA_VERSION + 1,
+            "Users": { "users": [] },
+        });
+        assert_eq!(
+            migrate(&mut document),
+            Err(ResultError::UnsupportedSchemaVersion(SCHEMA_VERSION + 1))
+        );
+    }
+}
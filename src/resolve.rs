@@ -0,0 +1,171 @@
+//! Matching names typed on the command line against exercises, sessions and profiles.
+//!
+//! A query picks the candidate whose own name it spells exactly. Failing that, it
+//! is compared with every name of every candidate ignoring case, then as a prefix,
+//! then allowing for a few typos; the first of these that matches anything decides,
+//! and matching more than one candidate there is an `Ambiguous` error.
+
+use crate::errors::{Lookup, Result, ResultError};
+
+/// Something a query can refer to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate<'a, T> {
+    pub value: T,
+    /// How the candidate is listed when a query is ambiguous.
+    pub label: String,
+    /// The candidate's own name first, then any others such as aliases.
+    pub names: Vec<&'a str>,
+    /// Names that only match ignoring case or as a prefix, never with typos. Dates
+    /// go here: one digit off is usually another valid date.
+    pub keys: Vec<&'a str>,
+}
+
+/// How many typos a query word of this length may contain and still match.
+pub(crate) fn allowed_typos(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// How well `query` (already lowercased) matches `candidate`; lower is better.
+fn rank<T>(query: &str, lowercase: &str, candidate: &Candidate<T>) -> Option<(u8, usize)> {
+    if candidate.names.first() == Some(&query) {
+        return Some((0, 0));
+    }
+    let all_names = || {
+        candidate
+            .names
+            .iter()
+            .chain(&candidate.keys)
+            .map(|name| name.to_lowercase())
+    };
+    if all_names().any(|name| name == lowercase) {
+        return Some((1, 0));
+    }
+    if all_names().any(|name| name.starts_with(lowercase)) {
+        return Some((2, 0));
+    }
+    candidate
+        .names
+        .iter()
+        .map(|name| strsim::damerau_levenshtein(&name.to_lowercase(), lowercase))
+        .filter(|distance| *distance <= allowed_typos(lowercase))
+        .min()
+        .map(|distance| (3, distance))
+}
+
+/// The candidate `query` refers to, or `None` if it matches none of them. `lookup`
+/// says what the candidates are, for the error when several match.
+pub fn resolve<T>(
+    query: &str,
+    lookup: Lookup,
+    candidates: Vec<Candidate<'_, T>>,
+) -> Result<Option<T>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(None);
+    }
+    let lowercase = query.to_lowercase();
+    let ranked: Vec<((u8, usize), Candidate<T>)> = candidates
+        .into_iter()
+        .filter_map(|candidate| Some((rank(query, &lowercase, &candidate)?, candidate)))
+        .collect();
+    let Some(best) = ranked.iter().map(|(rank, _)| *rank).min() else {
+        return Ok(None);
+    };
+
+    let mut matches: Vec<Candidate<T>> = ranked
+        .into_iter()
+        .filter(|(rank, _)| *rank == best)
+        .map(|(_, candidate)| candidate)
+        .collect();
+    if matches.len() > 1 {
+        return Err(ResultError::Ambiguous {
+            lookup,
+            query: query.to_string(),
+            candidates: matches
+                .into_iter()
+                .map(|candidate| candidate.label)
+                .collect(),
+        });
+    }
+    Ok(matches.pop().map(|candidate| candidate.value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<Candidate<'static, u32>> {
+        let candidate = |value, names: &[&'static str], keys: &[&'static str]| Candidate {
+            value,
+            label: names.first().copied().unwrap_or("?").to_string(),
+            names: names.to_vec(),
+            keys: keys.to_vec(),
+        };
+        vec![
+            candidate(1, &["bench press", "bench"], &[]),
+            candidate(2, &["incline bench press"], &[]),
+            candidate(3, &["Bench"], &[]),
+            candidate(4, &["dumbbell row"], &[]),
+            candidate(5, &["dumbbell curl"], &["01-15-2024"]),
+        ]
+    }
+
+    #[test]
+    fn test_resolve() {
+        // the exact own name beats an alias that differs only in case
+        assert_eq!(
+            resolve("Bench", Lookup::Exercise, candidates()).unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            resolve("BENCH PRESS", Lookup::Exercise, candidates()).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            resolve("incl", Lookup::Exercise, candidates()).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            resolve("dumbell row", Lookup::Exercise, candidates()).unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            resolve("01-15", Lookup::Exercise, candidates()).unwrap(),
+            Some(5)
+        );
+        assert_eq!(
+            resolve("01-16-2024", Lookup::Exercise, candidates()).unwrap(),
+            None
+        );
+        assert_eq!(
+            resolve("squat", Lookup::Exercise, candidates()).unwrap(),
+            None
+        );
+        assert_eq!(resolve(" ", Lookup::Exercise, candidates()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_resolve_ambiguous() {
+        let Err(ResultError::Ambiguous {
+            lookup,
+            query,
+            candidates: labels,
+        }) = resolve("dumbbell", Lookup::Exercise, candidates())
+        else {
+            panic!("expected an ambiguous match");
+        };
+        assert_eq!(lookup, Lookup::Exercise);
+        assert_eq!(query, "dumbbell");
+        assert_eq!(labels, vec!["dumbbell row", "dumbbell curl"]);
+
+        // case-insensitive matches of two different candidates
+        assert!(matches!(
+            resolve("bENCH", Lookup::Exercise, candidates()),
+            Err(ResultError::Ambiguous { .. })
+        ));
+    }
+}
@@ -1,42 +1,698 @@
-use crate::user_profile;
+use crate::errors::Result;
+use crate::errors::ResultError;
+use crate::exercise::{archived, Metric};
+use crate::storage::Storage;
+use crate::units::{Weight, WeightUnit};
+use crate::user_profile::update_current_user;
+use crate::workout_session::{find_current_session, update_current_session};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What kind of set was performed. Everything except `WarmUp` counts as a working set.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SetKind {
+    #[default]
+    Normal,
+    WarmUp,
+    Dropset,
+    Failure,
+    Amrap,
+    RestPause,
+}
+
+impl SetKind {
+    pub const ALL: [SetKind; 6] = [
+        SetKind::Normal,
+        SetKind::WarmUp,
+        SetKind::Dropset,
+        SetKind::Failure,
+        SetKind::Amrap,
+        SetKind::RestPause,
+    ];
+
+    /// The name used on the command line and in stored data, e.g. `warm-up`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SetKind::Normal => "normal",
+            SetKind::WarmUp => "warm-up",
+            SetKind::Dropset => "dropset",
+            SetKind::Failure => "failure",
+            SetKind::Amrap => "amrap",
+            SetKind::RestPause => "rest-pause",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<SetKind> {
+        SetKind::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+    pub fn is_warm_up(&self) -> bool {
+        *self == SetKind::WarmUp
+    }
+}
+
+impl fmt::Display for SetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How hard a set felt, either as a rating of perceived exertion or as reps in reserve.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Effort {
+    Rpe(f32),
+    Rir(u32),
+}
+
+impl Effort {
+    pub const MIN_RPE: f32 = 6.0;
+    pub const MAX_RPE: f32 = 10.0;
+    /// RIR covers the same range as RPE: 0 reps in reserve is RPE 10, 4 is RPE 6.
+    pub const MAX_RIR: u32 = 4;
+
+    /// RPE from 6 to 10 in half steps.
+    pub fn rpe(value: f32) -> Result<Effort> {
+        if !(Effort::MIN_RPE..=Effort::MAX_RPE).contains(&value) || (value * 2.0).fract() != 0.0 {
+            return Err(ResultError::InvalidInput(format!(
+                "RPE must be between {} and {} in steps of 0.5, got {}",
+                Effort::MIN_RPE,
+                Effort::MAX_RPE,
+                value
+            )));
+        }
+        Ok(Effort::Rpe(value))
+    }
+    pub fn rir(value: u32) -> Result<Effort> {
+        if value > Effort::MAX_RIR {
+            return Err(ResultError::InvalidInput(format!(
+                "RIR must be between 0 and {}, got {}",
+                Effort::MAX_RIR,
+                value
+            )));
+        }
+        Ok(Effort::Rir(value))
+    }
+    /// The effort on the RPE scale, so both kinds can be compared.
+    pub fn as_rpe(&self) -> f32 {
+        match self {
+            Effort::Rpe(rpe) => *rpe,
+            Effort::Rir(rir) => Effort::MAX_RPE - *rir as f32,
+        }
+    }
+}
+
+impl fmt::Display for Effort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effort::Rpe(rpe) => write!(f, "RPE {}", rpe),
+            Effort::Rir(rir) => write!(f, "{} RIR", rir),
+        }
+    }
+}
+
+/// The measurements of one set. Which of them apply depends on the metrics the
+/// exercise tracks; see `Exercise::validate`.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct SetValues {
+    pub reps: Option<u32>,
+    pub weight: Option<Weight>,
+    /// In seconds.
+    pub duration: Option<u32>,
+    /// In metres.
+    pub distance: Option<f32>,
+    pub calories: Option<u32>,
+    /// Average beats per minute.
+    pub heart_rate: Option<u32>,
+}
+
+impl SetValues {
+    pub fn has(&self, metric: Metric) -> bool {
+        match metric {
+            Metric::Reps => self.reps.is_some(),
+            Metric::Weight => self.weight.is_some(),
+            Metric::Duration => self.duration.is_some(),
+            Metric::Distance => self.distance.is_some(),
+            Metric::Calories => self.calories.is_some(),
+            Metric::HeartRate => self.heart_rate.is_some(),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Set {
     exercise: String,
-    reps: u32,
-    weight: f32,
-    is_dropset: bool,
+    reps: Option<u32>,
+    /// Always in kilograms, whatever unit the set was entered in.
+    weight: Option<f32>,
+    /// The unit the weight was entered in.
+    unit: WeightUnit,
+    /// In seconds.
+    duration: Option<u32>,
+    /// In metres.
+    distance: Option<f32>,
+    calories: Option<u32>,
+    heart_rate: Option<u32>,
+    kind: SetKind,
+    effort: Option<Effort>,
+    /// `None` for sets logged before the tracker recorded times.
+    performed_at: Option<DateTime<Utc>>,
 }
 
 impl Set {
-    pub fn new(exercise: String, reps: u32, weight: f32, is_dropset: bool) -> Set {
-        Set {
+    /// A set of reps with a weight, the common case.
+    pub fn new(exercise: String, reps: u32, weight: Weight, kind: SetKind) -> Set {
+        let values = SetValues {
+            reps: Some(reps),
+            weight: Some(weight),
+            ..SetValues::default()
+        };
+        Set::with_values(exercise, &values, kind)
+    }
+    pub fn with_values(exercise: String, values: &SetValues, kind: SetKind) -> Set {
+        let mut set = Set {
             exercise,
-            reps,
-            weight,
-            is_dropset,
-        }
+            reps: None,
+            weight: None,
+            unit: WeightUnit::default(),
+            duration: None,
+            distance: None,
+            calories: None,
+            heart_rate: None,
+            kind,
+            effort: None,
+            performed_at: Some(Utc::now()),
+        };
+        set.set_values(values);
+        set
     }
     pub fn get_exercise(&self) -> &String {
         &self.exercise
     }
-    pub fn get_reps(&self) -> &u32 {
-        &self.reps
+    pub fn set_exercise(&mut self, exercise: String) {
+        self.exercise = exercise;
+    }
+    pub fn get_reps(&self) -> Option<u32> {
+        self.reps
+    }
+    /// The weight in kilograms.
+    pub fn get_weight(&self) -> Option<f32> {
+        self.weight
+    }
+    pub fn get_unit(&self) -> WeightUnit {
+        self.unit
+    }
+    pub fn get_weight_in(&self, unit: WeightUnit) -> Option<Weight> {
+        self.weight.map(|kg| Weight::new(unit.from_kg(kg), unit))
+    }
+    /// Records which unit the weight was entered in without converting it, for
+    /// backends that store the kilograms and the unit separately.
+    pub fn with_entered_unit(mut self, unit: WeightUnit) -> Set {
+        self.unit = unit;
+        self
+    }
+    /// In seconds.
+    pub fn get_duration(&self) -> Option<u32> {
+        self.duration
+    }
+    /// In metres.
+    pub fn get_distance(&self) -> Option<f32> {
+        self.distance
+    }
+    pub fn get_calories(&self) -> Option<u32> {
+        self.calories
+    }
+    pub fn get_heart_rate(&self) -> Option<u32> {
+        self.heart_rate
+    }
+    /// The measurements, with the weight in the unit it was entered in.
+    pub fn get_values(&self) -> SetValues {
+        SetValues {
+            reps: self.reps,
+            weight: self.get_weight_in(self.unit),
+            duration: self.duration,
+            distance: self.distance,
+            calories: self.calories,
+            heart_rate: self.heart_rate,
+        }
+    }
+    /// Overwrites the measurements given in `values` and leaves the others alone.
+    pub fn set_values(&mut self, values: &SetValues) {
+        if let Some(reps) = values.reps {
+            self.reps = Some(reps);
+        }
+        if let Some(weight) = values.weight {
+            self.weight = Some(weight.to_kg());
+            self.unit = weight.get_unit();
+        }
+        if let Some(duration) = values.duration {
+            self.duration = Some(duration);
+        }
+        if let Some(distance) = values.distance {
+            self.distance = Some(distance);
+        }
+        if let Some(calories) = values.calories {
+            self.calories = Some(calories);
+        }
+        if let Some(heart_rate) = values.heart_rate {
+            self.heart_rate = Some(heart_rate);
+        }
+    }
+    /// Removes one measurement; clearing the weight keeps the entered unit.
+    pub fn clear(&mut self, metric: Metric) {
+        match metric {
+            Metric::Reps => self.reps = None,
+            Metric::Weight => self.weight = None,
+            Metric::Duration => self.duration = None,
+            Metric::Distance => self.distance = None,
+            Metric::Calories => self.calories = None,
+            Metric::HeartRate => self.heart_rate = None,
+        }
+    }
+    pub fn get_kind(&self) -> SetKind {
+        self.kind
+    }
+    pub fn with_effort(mut self, effort: Option<Effort>) -> Set {
+        self.effort = effort;
+        self
+    }
+    pub fn get_effort(&self) -> Option<Effort> {
+        self.effort
     }
-    pub fn get_weight(&self) -> &f32 {
-        &self.weight
+    pub fn with_performed_at(mut self, performed_at: Option<DateTime<Utc>>) -> Set {
+        self.performed_at = performed_at;
+        self
     }
-    pub fn get_is_dropset(&self) -> &bool {
-        &self.is_dropset
+    pub fn get_performed_at(&self) -> Option<DateTime<Utc>> {
+        self.performed_at
+    }
+    /// Clears and then overwrites the fields given in `edit`; the time the set was
+    /// performed is kept.
+    pub fn apply(&mut self, edit: SetEdit) {
+        if let Some(exercise) = edit.exercise {
+            self.exercise = exercise;
+        }
+        for metric in edit.clear {
+            self.clear(metric);
+        }
+        self.set_values(&edit.values);
+        if let Some(kind) = edit.kind {
+            self.kind = kind;
+        }
+        if edit.clear_effort {
+            self.effort = None;
+        }
+        if let Some(effort) = edit.effort {
+            self.effort = Some(effort);
+        }
     }
 }
 
-pub fn create_set(exercise: String, reps: u32, weight: f32, is_dropset: bool) -> () {
-    let new_set = Set::new(exercise, reps, weight, is_dropset);
-    let mut workout_s = crate::workout_session::get_current_session();
-    workout_s.add_set(new_set);
+/// Corrections to a logged set; `None` leaves a field unchanged.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SetEdit {
+    pub exercise: Option<String>,
+    pub values: SetValues,
+    /// Measurements to remove, e.g. when moving a set to an exercise that doesn't
+    /// track them.
+    pub clear: Vec<Metric>,
+    pub kind: Option<SetKind>,
+    pub effort: Option<Effort>,
+    pub clear_effort: bool,
+}
+
+/// Logs a set of `exercise` in the current session, after checking `values`
+/// against the metrics the exercise tracks.
+pub fn create_set(
+    storage: &dyn Storage,
+    exercise: String,
+    values: SetValues,
+    kind: SetKind,
+    effort: Option<Effort>,
+) -> Result<Set> {
+    let name = storage
+        .load_current_user()?
+        .ok_or(ResultError::NoCurrentUser)?;
+    // checked under the write lock, so a session finished or an exercise archived
+    // in the meantime can't slip a set through
+    storage.add_set(&name, &mut |user| {
+        let workout_s = find_current_session(user)?;
+        if workout_s.is_finished() {
+            return Err(ResultError::Conflict(format!(
+                "workout session #{} is already finished",
+                workout_s.get_id()
+            )));
+        }
+        let definition = user.resolve_exercise(&exercise)?;
+        if definition.is_archived() {
+            return Err(archived(definition.get_name()));
+        }
+        definition.validate(&values)?;
+        // `exercise` may have been an alias; sets always record the exercise's name
+        let new_set =
+            Set::with_values(definition.get_name().clone(), &values, kind).with_effort(effort);
+        Ok((workout_s.get_id(), new_set))
+    })
+}
+
+/// Changes set `number` of the current session and returns the updated set.
+/// The edited set has to fit the metrics of its exercise, and a new exercise
+/// can't be an archived one.
+pub fn edit_set(storage: &dyn Storage, number: usize, edit: SetEdit) -> Result<Set> {
+    if let Some(metric) = edit.clear.iter().find(|metric| edit.values.has(**metric)) {
+        return Err(ResultError::InvalidInput(format!(
+            "{} can't be both set and cleared",
+            metric
+        )));
+    }
+    if edit.clear_effort && edit.effort.is_some() {
+        return Err(ResultError::InvalidInput(
+            "effort can't be both set and cleared".to_string(),
+        ));
+    }
+    // resolved and validated under the write lock, like `create_set`
+    update_current_user(storage, |user| {
+        let mut edit = edit.clone();
+        if let Some(exercise) = &edit.exercise {
+            let definition = user.resolve_exercise(exercise)?;
+            if definition.is_archived() {
+                return Err(archived(definition.get_name()));
+            }
+            edit.exercise = Some(definition.get_name().clone());
+        }
+        let session = find_current_session(user)?;
+        let mut edited = session
+            .get_set(number)
+            .ok_or(ResultError::SetNotFound(number))?
+            .clone();
+        edited.apply(edit);
+        user.find_exercise(edited.get_exercise())
+            .ok_or(ResultError::ExerciseNotFound(edited.get_exercise().clone()))?
+            .validate(&edited.get_values())?;
+
+        let session_id = session.get_id();
+        let set = user
+            .get_workout_mut(session_id)
+            .and_then(|session| session.get_set_mut(number))
+            .ok_or(ResultError::SetNotFound(number))?;
+        *set = edited;
+        Ok(set.clone())
+    })
+}
+
+/// Removes set `number` from the current session and returns it.
+pub fn delete_set(storage: &dyn Storage, number: usize) -> Result<Set> {
+    update_current_session(storage, |workout_session| {
+        workout_session
+            .remove_set(number)
+            .ok_or(ResultError::SetNotFound(number))
+    })
+}
+
+/// Removes the most recently added set of the current session and returns it.
+pub fn undo_last_set(storage: &dyn Storage) -> Result<Set> {
+    update_current_session(storage, |workout_session| {
+        workout_session.remove_last_set().ok_or(ResultError::NoSets)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exercise::{archive_exercise, create_exercise, Equipment, Exercise, Metric};
+    use crate::storage::MemoryStorage;
+    use crate::user_profile::{choose_profile, create_profile};
+    use crate::workout_session::{get_current_session, start};
 
-    crate::workout_session::save_current_session(&workout_s).unwrap();
-    crate::user_profile::save_user_profile(&user_profile::get_current_user().unwrap()).unwrap();
+    fn add_exercise(storage: &MemoryStorage, name: &str, metrics: Vec<Metric>) {
+        let exercise =
+            Exercise::new(name.to_string(), None, vec![], Equipment::Other).with_metrics(metrics);
+        create_exercise(storage, exercise).unwrap();
+    }
+
+    fn storage_with_session() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        create_profile(&storage, "test".to_string()).unwrap();
+        choose_profile(&storage, "test".to_string()).unwrap();
+        start(&storage, None).unwrap();
+        storage
+    }
+
+    fn lift(reps: u32, weight: Weight) -> SetValues {
+        SetValues {
+            reps: Some(reps),
+            weight: Some(weight),
+            ..SetValues::default()
+        }
+    }
+
+    #[test]
+    fn test_effort_validation() {
+        assert_eq!(Effort::rpe(8.5), Ok(Effort::Rpe(8.5)));
+        assert!(Effort::rpe(8.3).is_err());
+        assert!(Effort::rpe(5.5).is_err());
+        assert!(Effort::rpe(10.5).is_err());
+        assert_eq!(Effort::rir(2).map(|effort| effort.as_rpe()), Ok(8.0));
+        assert!(Effort::rir(5).is_err());
+    }
+
+    #[test]
+    fn test_edit_set() {
+        let storage = storage_with_session();
+        let set = create_set(
+            &storage,
+            "squat".to_string(),
+            lift(100, Weight::kg(8.0)),
+            SetKind::Normal,
+            None,
+        )
+        .unwrap();
+
+        let edit = SetEdit {
+            values: lift(8, Weight::new(220.0, WeightUnit::Lb)),
+            effort: Some(Effort::Rpe(9.0)),
+            ..SetEdit::default()
+        };
+        let edited = edit_set(&storage, 1, edit).unwrap();
+        assert_eq!(edited.get_reps(), Some(8));
+        assert_eq!(edited.get_unit(), WeightUnit::Lb);
+        assert_eq!(
+            edited.get_weight_in(WeightUnit::Lb).unwrap().to_string(),
+            "220 lb"
+        );
+        assert_eq!(edited.get_effort(), Some(Effort::Rpe(9.0)));
+        assert_eq!(edited.get_performed_at(), set.get_performed_at());
+        assert_eq!(
+            get_current_session(&storage).unwrap().get_sets(),
+            &vec![edited]
+        );
+
+        let edit = SetEdit {
+            exercise: Some("floor press".to_string()),
+            ..SetEdit::default()
+        };
+        assert_eq!(
+            edit_set(&storage, 1, edit.clone()),
+            Err(ResultError::ExerciseNotFound("floor press".to_string()))
+        );
+        add_exercise(&storage, "floor press", Metric::DEFAULT.to_vec());
+        assert_eq!(
+            edit_set(&storage, 1, edit).unwrap().get_exercise(),
+            "floor press"
+        );
+
+        // moving the set to a timed exercise needs a duration and no weight
+        let edit = SetEdit {
+            exercise: Some("plank".to_string()),
+            ..SetEdit::default()
+        };
+        assert!(matches!(
+            edit_set(&storage, 1, edit.clone()),
+            Err(ResultError::InvalidInput(_))
+        ));
+        let edit = SetEdit {
+            values: SetValues {
+                duration: Some(60),
+                ..SetValues::default()
+            },
+            clear: vec![Metric::Reps, Metric::Weight],
+            clear_effort: true,
+            ..edit
+        };
+        let edited = edit_set(&storage, 1, edit).unwrap();
+        assert_eq!(edited.get_exercise(), "plank");
+        assert_eq!(
+            edited.get_values(),
+            SetValues {
+                duration: Some(60),
+                ..SetValues::default()
+            }
+        );
+        assert_eq!(edited.get_effort(), None);
+
+        let edit = SetEdit {
+            values: SetValues {
+                duration: Some(30),
+                ..SetValues::default()
+            },
+            clear: vec![Metric::Duration],
+            ..SetEdit::default()
+        };
+        assert_eq!(
+            edit_set(&storage, 1, edit),
+            Err(ResultError::InvalidInput(
+                "duration can't be both set and cleared".to_string()
+            ))
+        );
+        assert_eq!(
+            edit_set(&storage, 2, SetEdit::default()),
+            Err(ResultError::SetNotFound(2))
+        );
+    }
+
+    #[test]
+    fn test_edit_set_onto_archived_exercise() {
+        let storage = storage_with_session();
+        create_set(
+            &storage,
+            "squat".to_string(),
+            lift(5, Weight::kg(100.0)),
+            SetKind::Normal,
+            None,
+        )
+        .unwrap();
+        archive_exercise(&storage, "front squat").unwrap();
+
+        let edit = SetEdit {
+            exercise: Some("front squat".to_string()),
+            ..SetEdit::default()
+        };
+        assert_eq!(edit_set(&storage, 1, edit), Err(archived("front squat")));
+        assert_eq!(
+            get_current_session(&storage).unwrap().get_sets()[0].get_exercise(),
+            "squat"
+        );
+
+        // sets already logged of an archived exercise can still be corrected
+        archive_exercise(&storage, "squat").unwrap();
+        let edit = SetEdit {
+            kind: Some(SetKind::Failure),
+            ..SetEdit::default()
+        };
+        assert_eq!(
+            edit_set(&storage, 1, edit).unwrap().get_kind(),
+            SetKind::Failure
+        );
+    }
+
+    #[test]
+    fn test_delete_set() {
+        let storage = storage_with_session();
+        let first = create_set(
+            &storage,
+            "squat".to_string(),
+            lift(5, Weight::kg(100.0)),
+            SetKind::Normal,
+            None,
+        )
+        .unwrap();
+        let second = create_set(
+            &storage,
+            "squat".to_string(),
+            lift(5, Weight::kg(110.0)),
+            SetKind::Normal,
+            None,
+        )
+        .unwrap();
+        let third = create_set(
+            &storage,
+            "squat".to_string(),
+            lift(5, Weight::kg(120.0)),
+            SetKind::Normal,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(delete_set(&storage, 0), Err(ResultError::SetNotFound(0)));
+        assert_eq!(delete_set(&storage, 2), Ok(second));
+        assert_eq!(undo_last_set(&storage), Ok(third));
+        assert_eq!(
+            get_current_session(&storage).unwrap().get_sets(),
+            &vec![first]
+        );
+
+        undo_last_set(&storage).unwrap();
+        assert_eq!(undo_last_set(&storage), Err(ResultError::NoSets));
+    }
+
+    #[test]
+    fn test_create_set_checks_metrics() {
+        let storage = storage_with_session();
+
+        let plank = SetValues {
+            duration: Some(90),
+            ..SetValues::default()
+        };
+        let set = create_set(&storage, "plank".to_string(), plank, SetKind::Normal, None).unwrap();
+        assert_eq!(set.get_duration(), Some(90));
+        assert_eq!(set.get_reps(), None);
+        assert_eq!(set.get_values(), plank);
+
+        // heart rate is optional, distance isn't
+        let run = SetValues {
+            duration: Some(600),
+            distance: Some(2000.0),
+            ..SetValues::default()
+        };
+        assert!(create_set(&storage, "run".to_string(), run, SetKind::Normal, None).is_ok());
+        let no_distance = SetValues {
+            distance: None,
+            ..run
+        };
+        assert_eq!(
+            create_set(
+                &storage,
+                "run".to_string(),
+                no_distance,
+                SetKind::Normal,
+                None
+            ),
+            Err(ResultError::InvalidInput(
+                "run needs distance on every set".to_string()
+            ))
+        );
+        assert_eq!(
+            create_set(
+                &storage,
+                "plank".to_string(),
+                lift(5, Weight::kg(20.0)),
+                SetKind::Normal,
+                None
+            ),
+            Err(ResultError::InvalidInput(
+                "plank doesn't track reps".to_string()
+            ))
+        );
+        assert_eq!(
+            create_set(
+                &storage,
+                "sled push".to_string(),
+                run,
+                SetKind::Normal,
+                None
+            ),
+            Err(ResultError::ExerciseNotFound("sled push".to_string()))
+        );
+
+        // built-in exercises can be logged by alias without creating them
+        let set = create_set(
+            &storage,
+            "OHP".to_string(),
+            lift(5, Weight::kg(50.0)),
+            SetKind::Normal,
+            None,
+        )
+        .unwrap();
+        assert_eq!(set.get_exercise(), "overhead press");
+    }
 }
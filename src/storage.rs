@@ -0,0 +1,195 @@
+use crate::errors::ResultError;
+use crate::errors::{FileError, Result};
+use crate::set::Set;
+use crate::user_profile::{UserProfile, Users};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod json;
+mod memory;
+mod sqlite;
+
+pub use json::JsonStorage;
+pub use memory::MemoryStorage;
+pub use sqlite::{SqliteStorage, DATABASE_FILE_NAME};
+
+/// Persistence backend for user profiles and the current-user pointer.
+///
+/// The profile, session and exercise modules only talk to a `Storage`, so they
+/// never touch the filesystem directly.
+pub trait Storage {
+    fn load_users(&self) -> Result<Users>;
+    fn save_users(&self, users: &Users) -> Result<()>;
+    /// Returns the name of the active profile, or `None` if none has been chosen yet.
+    fn load_current_user(&self) -> Result<Option<String>>;
+    fn save_current_user(&self, name: &str) -> Result<()>;
+
+    /// Loads every profile, lets `f` modify them and saves the result.
+    ///
+    /// Backends that can be shared between processes must override this so that nobody
+    /// else writes in between the load and the save.
+    fn update_users(&self, f: &mut dyn FnMut(&mut Users) -> Result<()>) -> Result<()> {
+        let mut users = self.load_users()?;
+        f(&mut users)?;
+        self.save_users(&users)
+    }
+
+    /// Like `update_users`, but for the single profile called `name`.
+    fn update_user(
+        &self,
+        name: &str,
+        f: &mut dyn FnMut(&mut UserProfile) -> Result<()>,
+    ) -> Result<()> {
+        self.update_users(&mut |users| {
+            let user = users
+                .get_user_mut(&name.to_string())
+                .ok_or(ResultError::UserNotFound(name.to_string()))?;
+            f(user)
+        })
+    }
+
+    /// Adds or replaces a single profile.
+    fn save_user(&self, user: &UserProfile) -> Result<()> {
+        self.update_users(&mut |users| {
+            users.upsert_user(user.clone());
+            Ok(())
+        })
+    }
+
+    /// Rolls the stored profiles back to the `generation`-th most recent backup.
+    fn restore_backup(&self, _generation: usize) -> Result<()> {
+        Err(ResultError::Unsupported(
+            "this storage backend does not keep backups".to_string(),
+        ))
+    }
+
+    /// Appends the set built by `f` to a workout session of the given profile and
+    /// returns it. `f` sees the profile under the same lock as the write, so the
+    /// checks it makes still hold when the set is added; it returns the session's
+    /// id along with the set.
+    fn add_set(
+        &self,
+        user: &str,
+        f: &mut dyn FnMut(&UserProfile) -> Result<(u32, Set)>,
+    ) -> Result<Set> {
+        let mut added = None;
+        self.update_user(user, &mut |profile| {
+            let (session, set) = f(profile)?;
+            profile
+                .get_workout_mut(session)
+                .ok_or(ResultError::SessionNotFound(session.to_string()))?
+                .add_set(set.clone());
+            added = Some(set);
+            Ok(())
+        })?;
+        added.ok_or(ResultError::UserNotFound(user.to_string()))
+    }
+}
+
+/// Environment variable that overrides where the data files live.
+pub const HOME_ENV_VAR: &str = "GYM_TRACKER_HOME";
+const APP_DIR_NAME: &str = "gym_tracker";
+
+/// Picks the data directory: an explicit `--data-dir`, then `$GYM_TRACKER_HOME`,
+/// then `$XDG_DATA_HOME/gym_tracker`, falling back to `~/.local/share/gym_tracker`.
+/// The directory is created if it doesn't exist yet.
+pub fn data_dir(explicit: Option<&Path>) -> Result<PathBuf> {
+    let dir = resolve_data_dir(
+        explicit,
+        env::var_os(HOME_ENV_VAR).map(PathBuf::from),
+        env::var_os("XDG_DATA_HOME").map(PathBuf::from),
+        env::var_os("HOME").map(PathBuf::from),
+    )?;
+    fs::create_dir_all(&dir).map_err(FileError::IoError)?;
+    Ok(dir)
+}
+
+fn resolve_data_dir(
+    explicit: Option<&Path>,
+    tracker_home: Option<PathBuf>,
+    xdg_data_home: Option<PathBuf>,
+    home: Option<PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(dir) = explicit {
+        return Ok(dir.to_path_buf());
+    }
+    if let Some(dir) = tracker_home.filter(|dir| !dir.as_os_str().is_empty()) {
+        return Ok(dir);
+    }
+    // the XDG spec says relative paths are invalid and should be ignored
+    if let Some(dir) = xdg_data_home.filter(|dir| dir.is_absolute()) {
+        return Ok(dir.join(APP_DIR_NAME));
+    }
+    match home.filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(home) => Ok(home.join(".local").join("share").join(APP_DIR_NAME)),
+        None => Err(ResultError::NoDataDir),
+    }
+}
+
+/// Copies every profile and the current-user pointer from one backend into another.
+///
+/// Refuses to run if `to` already holds profiles, so an import can't silently merge
+/// two histories. Returns the number of imported profiles.
+pub fn import(from: &dyn Storage, to: &dyn Storage) -> Result<usize> {
+    if !to.load_users()?.list().is_empty() {
+        return Err(ResultError::Conflict(
+            "destination storage already contains profiles".to_string(),
+        ));
+    }
+
+    let users = from.load_users()?;
+    to.save_users(&users)?;
+    if let Some(name) = from.load_current_user()? {
+        to.save_current_user(&name)?;
+    }
+
+    Ok(users.list().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_data_dir_precedence() {
+        let explicit = Path::new("/explicit");
+        let home = || Some(PathBuf::from("/home/test"));
+
+        assert_eq!(
+            resolve_data_dir(
+                Some(explicit),
+                Some("/tracker".into()),
+                Some("/xdg".into()),
+                home()
+            ),
+            Ok(PathBuf::from("/explicit"))
+        );
+        assert_eq!(
+            resolve_data_dir(None, Some("/tracker".into()), Some("/xdg".into()), home()),
+            Ok(PathBuf::from("/tracker"))
+        );
+        assert_eq!(
+            resolve_data_dir(None, None, Some("/xdg".into()), home()),
+            Ok(PathBuf::from("/xdg/gym_tracker"))
+        );
+        assert_eq!(
+            resolve_data_dir(None, None, None, home()),
+            Ok(PathBuf::from("/home/test/.local/share/gym_tracker"))
+        );
+    }
+
+    #[test]
+    fn test_resolve_data_dir_ignores_relative_xdg() {
+        assert_eq!(
+            resolve_data_dir(
+                None,
+                None,
+                Some("relative".into()),
+                Some("/home/test".into())
+            ),
+            Ok(PathBuf::from("/home/test/.local/share/gym_tracker"))
+        );
+        assert!(resolve_data_dir(None, None, None, None).is_err());
+    }
+}
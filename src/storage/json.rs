@@ -0,0 +1,127 @@
+use crate::errors::Result;
+use crate::errors::ResultError;
+use crate::storage::Storage;
+use crate::user_profile::{CurrentUser, Users};
+use crate::utils::{
+    lock_file, read_file, restore_backup, update_file, FileContents, FileLock, LOCK_TIMEOUT,
+};
+use std::path::{Path, PathBuf};
+
+const FILE_NAME: &str = "user_profile.json";
+const CURRENT_USER_FILE_NAME: &str = "current_user.json";
+
+/// Stores every profile in one JSON document and the active profile in a second one.
+#[derive(Debug, Clone)]
+pub struct JsonStorage {
+    users_path: PathBuf,
+    current_user_path: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn new(users_path: impl Into<PathBuf>, current_user_path: impl Into<PathBuf>) -> Self {
+        JsonStorage {
+            users_path: users_path.into(),
+            current_user_path: current_user_path.into(),
+        }
+    }
+    /// Uses the standard file names inside `dir`.
+    pub fn in_dir(dir: &Path) -> Self {
+        JsonStorage::new(dir.join(FILE_NAME), dir.join(CURRENT_USER_FILE_NAME))
+    }
+    pub fn users_path(&self) -> &Path {
+        &self.users_path
+    }
+    pub fn current_user_path(&self) -> &Path {
+        &self.current_user_path
+    }
+
+    fn lock(&self) -> Result<FileLock> {
+        lock_file(path_str(&self.users_path)?, LOCK_TIMEOUT)
+    }
+
+    fn write_users(&self, users: &Users) -> Result<()> {
+        update_file(
+            path_str(&self.users_path)?,
+            &FileContents::Users(users.clone()),
+        )
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or(ResultError::InvalidPath(path.to_path_buf()))
+}
+
+impl Storage for JsonStorage {
+    fn load_users(&self) -> Result<Users> {
+        match read_file(path_str(&self.users_path)?)? {
+            FileContents::Users(users) => Ok(users),
+            _ => Err(ResultError::InvalidDocument(format!(
+                "{} does not contain user profiles",
+                self.users_path.display()
+            ))),
+        }
+    }
+
+    fn save_users(&self, users: &Users) -> Result<()> {
+        let _lock = self.lock()?;
+        self.write_users(users)
+    }
+
+    fn update_users(&self, f: &mut dyn FnMut(&mut Users) -> Result<()>) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut users = self.load_users()?;
+        f(&mut users)?;
+        self.write_users(&users)
+    }
+
+    fn load_current_user(&self) -> Result<Option<String>> {
+        // a missing file reads back as an empty `Users` document
+        match read_file(path_str(&self.current_user_path)?)? {
+            FileContents::CurrentUser(current_user) => Ok(Some(current_user.get_name().clone())),
+            FileContents::Users(_) => Ok(None),
+        }
+    }
+
+    fn restore_backup(&self, generation: usize) -> Result<()> {
+        let _lock = self.lock()?;
+        restore_backup(path_str(&self.users_path)?, generation)
+    }
+
+    fn save_current_user(&self, name: &str) -> Result<()> {
+        if !self.load_users()?.user_exists(&name.to_string()) {
+            return Err(ResultError::UserNotFound(name.to_string()));
+        }
+
+        update_file(
+            path_str(&self.current_user_path)?,
+            &FileContents::CurrentUser(CurrentUser::new(name.to_string())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user_profile::UserProfile;
+    use crate::utils::test_utils::{clear_path, TEST_FILE};
+
+    #[test]
+    fn test_save_and_load_users() {
+        let users_path = TEST_FILE.to_string() + "_storage_users";
+        let current_path = TEST_FILE.to_string() + "_storage_current";
+        let storage = JsonStorage::new(&users_path, &current_path);
+
+        let mut users = Users::new();
+        users.add_user(UserProfile::new("test".to_string()));
+        storage.save_users(&users).unwrap();
+        assert_eq!(storage.load_users(), Ok(users));
+
+        assert_eq!(storage.load_current_user(), Ok(None));
+        storage.save_current_user("test").unwrap();
+        assert_eq!(storage.load_current_user(), Ok(Some("test".to_string())));
+
+        clear_path(&users_path);
+        clear_path(&current_path);
+    }
+}
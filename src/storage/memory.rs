@@ -0,0 +1,41 @@
+use crate::errors::Result;
+use crate::errors::ResultError;
+use crate::storage::Storage;
+use crate::user_profile::Users;
+use std::cell::RefCell;
+
+/// Keeps everything in memory; nothing outlives the value. Mostly useful for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    users: RefCell<Users>,
+    current_user: RefCell<Option<String>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load_users(&self) -> Result<Users> {
+        Ok(self.users.borrow().clone())
+    }
+
+    fn save_users(&self, users: &Users) -> Result<()> {
+        *self.users.borrow_mut() = users.clone();
+        Ok(())
+    }
+
+    fn load_current_user(&self) -> Result<Option<String>> {
+        Ok(self.current_user.borrow().clone())
+    }
+
+    fn save_current_user(&self, name: &str) -> Result<()> {
+        if !self.users.borrow().user_exists(&name.to_string()) {
+            return Err(ResultError::UserNotFound(name.to_string()));
+        }
+        *self.current_user.borrow_mut() = Some(name.to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,1201 @@
+use crate::bodyweight::BodyweightEntry;
+use crate::errors::Result;
+use crate::errors::ResultError;
+use crate::exercise::{Equipment, Exercise, Loading, Metric, MuscleGroup};
+use crate::migrations::structure_free_text;
+use crate::set::{Effort, Set, SetKind, SetValues};
+use crate::storage::Storage;
+use crate::units::{Weight, WeightUnit};
+use crate::user_profile::{UserProfile, Users};
+use crate::utils::LOCK_TIMEOUT;
+use crate::workout_session::WorkoutSession;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+use rusqlite::{
+    params, Connection, ErrorCode, OptionalExtension, Transaction, TransactionBehavior,
+};
+use std::path::{Path, PathBuf};
+
+pub const DATABASE_FILE_NAME: &str = "gym_tracker.db";
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE users (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        chosen_workout_session TEXT,
+        chosen_exercise TEXT
+    );
+    CREATE TABLE current_user (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        name TEXT NOT NULL REFERENCES users(name) ON DELETE CASCADE ON UPDATE CASCADE
+    );
+    CREATE TABLE exercises (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        equipment TEXT NOT NULL
+    );
+    CREATE TABLE exercise_muscle_groups (
+        exercise_id INTEGER NOT NULL REFERENCES exercises(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        muscle_group TEXT NOT NULL
+    );
+    CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE sets (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        exercise TEXT NOT NULL,
+        reps INTEGER NOT NULL,
+        weight REAL NOT NULL,
+        is_dropset INTEGER NOT NULL
+    );
+    CREATE INDEX exercises_user ON exercises(user_id);
+    CREATE INDEX sessions_user ON sessions(user_id);
+    CREATE INDEX sets_session ON sets(session_id);
+"#,
+    r#"
+    -- sessions get a per-user id, shown in the CLI as the session number
+    ALTER TABLE sessions ADD COLUMN number INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE sessions ADD COLUMN title TEXT;
+    UPDATE sessions SET number = position + 1;
+    CREATE UNIQUE INDEX sessions_user_number ON sessions(user_id, number);
+
+    ALTER TABLE users ADD COLUMN next_session_id INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE users ADD COLUMN chosen_session_number INTEGER;
+    UPDATE users SET
+        next_session_id = 1 + (SELECT COUNT(*) FROM sessions WHERE user_id = users.id),
+        chosen_session_number = (
+            SELECT MIN(number) FROM sessions
+            WHERE user_id = users.id AND name = users.chosen_workout_session
+        );
+    ALTER TABLE users DROP COLUMN chosen_workout_session;
+    ALTER TABLE users RENAME COLUMN chosen_session_number TO chosen_workout_session;
+"#,
+    r#"
+    -- sessions only had an MM-DD-YYYY date; start them at noon UTC on that day
+    ALTER TABLE sessions ADD COLUMN started_at TEXT NOT NULL DEFAULT '';
+    ALTER TABLE sessions ADD COLUMN ended_at TEXT;
+    UPDATE sessions SET started_at =
+        substr(name, 7, 4) || '-' || substr(name, 1, 2) || '-' || substr(name, 4, 2)
+        || ' 12:00:00+00:00';
+    ALTER TABLE sessions DROP COLUMN name;
+    ALTER TABLE sets ADD COLUMN performed_at TEXT;
+"#,
+    r#"
+    ALTER TABLE sets ADD COLUMN kind TEXT NOT NULL DEFAULT 'normal';
+    UPDATE sets SET kind = 'dropset' WHERE is_dropset;
+    ALTER TABLE sets DROP COLUMN is_dropset;
+"#,
+    r#"
+    -- at most one of these is set
+    ALTER TABLE sets ADD COLUMN rpe REAL;
+    ALTER TABLE sets ADD COLUMN rir INTEGER;
+"#,
+    r#"
+    -- weights were unitless; treat them as the kilograms they're now stored in
+    ALTER TABLE sets ADD COLUMN unit TEXT NOT NULL DEFAULT 'kg';
+    ALTER TABLE users ADD COLUMN weight_unit TEXT NOT NULL DEFAULT 'kg';
+"#,
+    r#"
+    ALTER TABLE exercises ADD COLUMN loading TEXT NOT NULL DEFAULT 'external';
+    CREATE TABLE bodyweights (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        recorded_at TEXT NOT NULL,
+        weight REAL NOT NULL
+    );
+    CREATE INDEX bodyweights_user ON bodyweights(user_id);
+"#,
+    r#"
+    -- exercises so far all tracked reps and weight
+    CREATE TABLE exercise_metrics (
+        exercise_id INTEGER NOT NULL REFERENCES exercises(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        metric TEXT NOT NULL
+    );
+    INSERT INTO exercise_metrics (exercise_id, position, metric)
+        SELECT id, 0, 'reps' FROM exercises
+        UNION ALL SELECT id, 1, 'weight' FROM exercises;
+
+    -- reps and weight become optional, which SQLite can only do by rebuilding the table
+    CREATE TABLE sets_new (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        exercise TEXT NOT NULL,
+        reps INTEGER,
+        weight REAL,
+        unit TEXT NOT NULL,
+        duration INTEGER,
+        distance REAL,
+        calories INTEGER,
+        heart_rate INTEGER,
+        kind TEXT NOT NULL,
+        rpe REAL,
+        rir INTEGER,
+        performed_at TEXT
+    );
+    INSERT INTO sets_new
+        (id, session_id, position, exercise, reps, weight, unit, kind, rpe, rir, performed_at)
+        SELECT id, session_id, position, exercise, reps, weight, unit, kind, rpe, rir, performed_at
+        FROM sets;
+    DROP TABLE sets;
+    ALTER TABLE sets_new RENAME TO sets;
+    CREATE INDEX sets_session ON sets(session_id);
+"#,
+    r#"
+    ALTER TABLE exercises ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+"#,
+    r#"
+    CREATE TABLE exercise_aliases (
+        exercise_id INTEGER NOT NULL REFERENCES exercises(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        alias TEXT NOT NULL
+    );
+"#,
+    r#"
+    -- the free text is mapped onto muscle groups and equipment by `migrate_rows`
+    ALTER TABLE exercise_muscle_groups ADD COLUMN role TEXT NOT NULL DEFAULT 'primary';
+"#,
+];
+
+impl FromSql for SetKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        SetKind::from_name(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl FromSql for Loading {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Loading::from_name(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl FromSql for Metric {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Metric::from_name(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl FromSql for MuscleGroup {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        MuscleGroup::from_name(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl FromSql for Equipment {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Equipment::from_name(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl FromSql for WeightUnit {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        WeightUnit::from_name(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+/// Keeps profiles in an embedded SQLite database with one table per entity,
+/// so adding a set is a single insert instead of a rewrite of the whole history.
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens the standard database file inside `dir`.
+    pub fn open_in_dir(dir: &Path) -> Result<Self> {
+        Self::open(dir.join(DATABASE_FILE_NAME))
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        connection.busy_timeout(LOCK_TIMEOUT)?;
+        let storage = SqliteStorage { connection };
+        storage.migrate()?;
+        Ok(storage)
+    }
+
+    /// Starts a write transaction, taking the database write lock up front so that a
+    /// read-modify-write can't interleave with another process.
+    fn begin(&self) -> Result<Transaction<'_>> {
+        Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate).map_err(|e| {
+            match e.sqlite_error_code() {
+                Some(ErrorCode::DatabaseBusy) => ResultError::LockTimeout(PathBuf::from(
+                    self.connection.path().unwrap_or_default(),
+                )),
+                _ => e.into(),
+            }
+        })
+    }
+
+    /// Runs the migrations the database hasn't seen yet. The version is read again
+    /// under the write lock, so two processes opening an old database at the same
+    /// time don't both apply a migration.
+    fn migrate(&self) -> Result<()> {
+        let version: usize = self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version >= MIGRATIONS.len() {
+            return Ok(());
+        }
+
+        loop {
+            let tx = self.begin()?;
+            let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            let Some(migration) = MIGRATIONS.get(version) else {
+                return Ok(());
+            };
+            tx.execute_batch(migration)?;
+            migrate_rows(&tx, version)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+        }
+    }
+
+    fn load_user(&self, id: i64, name: String) -> Result<UserProfile> {
+        let (chosen_workout_session, chosen_exercise, next_session_id, weight_unit): (
+            Option<u32>,
+            Option<String>,
+            u32,
+            WeightUnit,
+        ) = self.connection.query_row(
+            "SELECT chosen_workout_session, chosen_exercise, next_session_id, weight_unit
+             FROM users WHERE id = ?1",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+
+        let mut user = UserProfile::new(name);
+        user.set_next_session_id(next_session_id);
+        user.set_weight_unit(weight_unit);
+        if let Some(session) = chosen_workout_session {
+            user.set_chosen_workout_session(session);
+        }
+        if let Some(exercise) = chosen_exercise {
+            user.set_chosen_exercise(exercise);
+        }
+
+        for exercise in self.load_exercises(id)? {
+            user.add_exercise(exercise);
+        }
+        for entry in self.load_bodyweights(id)? {
+            user.add_bodyweight(entry);
+        }
+        for session in self.load_sessions(id)? {
+            user.add_workout(session);
+        }
+        Ok(user)
+    }
+
+    fn load_exercises(&self, user_id: i64) -> Result<Vec<Exercise>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name, description, equipment, loading, archived FROM exercises
+             WHERE user_id = ?1 ORDER BY position",
+        )?;
+        let rows = statement
+            .query_map([user_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Equipment>(3)?,
+                    row.get::<_, Loading>(4)?,
+                    row.get::<_, bool>(5)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut muscle_groups = self.connection.prepare(
+            "SELECT muscle_group FROM exercise_muscle_groups
+             WHERE exercise_id = ?1 AND role = ?2 ORDER BY position",
+        )?;
+        let mut metrics = self.connection.prepare(
+            "SELECT metric FROM exercise_metrics WHERE exercise_id = ?1 ORDER BY position",
+        )?;
+        let mut aliases = self.connection.prepare(
+            "SELECT alias FROM exercise_aliases WHERE exercise_id = ?1 ORDER BY position",
+        )?;
+        let mut exercises = Vec::new();
+        for (id, name, description, equipment, loading, archived) in rows {
+            let mut muscles = |role: &str| {
+                muscle_groups
+                    .query_map(params![id, role], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<MuscleGroup>>>()
+            };
+            let primary = muscles("primary")?;
+            let secondary = muscles("secondary")?;
+            let exercise_metrics = metrics
+                .query_map([id], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<Metric>>>()?;
+            let mut exercise = Exercise::new(name, Some(description), primary, equipment)
+                .with_secondary_muscles(secondary)
+                .with_loading(loading)
+                .with_metrics(exercise_metrics)
+                .with_aliases(
+                    aliases
+                        .query_map([id], |row| row.get(0))?
+                        .collect::<rusqlite::Result<Vec<String>>>()?,
+                );
+            exercise.set_archived(archived);
+            exercises.push(exercise);
+        }
+        Ok(exercises)
+    }
+
+    fn load_bodyweights(&self, user_id: i64) -> Result<Vec<BodyweightEntry>> {
+        let mut statement = self.connection.prepare(
+            "SELECT recorded_at, weight FROM bodyweights
+             WHERE user_id = ?1 ORDER BY position",
+        )?;
+        let entries = statement
+            .query_map([user_id], |row| {
+                Ok(BodyweightEntry::new(row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    fn load_sessions(&self, user_id: i64) -> Result<Vec<WorkoutSession>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, number, title, started_at, ended_at FROM sessions
+                 WHERE user_id = ?1 ORDER BY position",
+        )?;
+        let rows = statement
+            .query_map([user_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut sets = self.connection.prepare(
+            "SELECT exercise, reps, weight, unit, duration, distance, calories, heart_rate,
+                    kind, rpe, rir, performed_at
+             FROM sets WHERE session_id = ?1 ORDER BY position",
+        )?;
+        let mut sessions = Vec::new();
+        for (id, number, title, started_at, ended_at) in rows {
+            let session_sets = sets
+                .query_map([id], |row| {
+                    // the weight is stored in kilograms next to the unit it was entered in
+                    let values = SetValues {
+                        reps: row.get(1)?,
+                        weight: row.get::<_, Option<f32>>(2)?.map(Weight::kg),
+                        duration: row.get(4)?,
+                        distance: row.get(5)?,
+                        calories: row.get(6)?,
+                        heart_rate: row.get(7)?,
+                    };
+                    Ok(Set::with_values(row.get(0)?, &values, row.get(8)?)
+                        .with_entered_unit(row.get(3)?)
+                        .with_effort(effort(row.get(9)?, row.get(10)?))
+                        .with_performed_at(row.get(11)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            sessions.push(WorkoutSession::from_parts(
+                number,
+                title,
+                started_at,
+                ended_at,
+                session_sets,
+            ));
+        }
+        Ok(sessions)
+    }
+}
+
+/// Rewrites rows that a migration's SQL can't convert on its own; runs right after
+/// `MIGRATIONS[index]`, in the same transaction.
+fn migrate_rows(tx: &Transaction, index: usize) -> Result<()> {
+    match index {
+        10 => structure_exercises(tx),
+        _ => Ok(()),
+    }
+}
+
+fn structure_exercises(tx: &Transaction) -> Result<()> {
+    let exercises = tx
+        .prepare("SELECT id, description, equipment FROM exercises")?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (id, description, equipment) in exercises {
+        let groups = tx
+            .prepare(
+                "SELECT muscle_group FROM exercise_muscle_groups
+                 WHERE exercise_id = ?1 ORDER BY position",
+            )?
+            .query_map([id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let structured = structure_free_text(&description, &groups, &equipment);
+
+        tx.execute(
+            "UPDATE exercises SET description = ?2, equipment = ?3 WHERE id = ?1",
+            params![id, structured.description, structured.equipment.as_str()],
+        )?;
+        tx.execute(
+            "DELETE FROM exercise_muscle_groups WHERE exercise_id = ?1",
+            [id],
+        )?;
+        for (position, muscle) in structured.primary_muscles.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO exercise_muscle_groups (exercise_id, position, muscle_group, role)
+                 VALUES (?1, ?2, ?3, 'primary')",
+                params![id, position, muscle.as_str()],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn user_id(tx: &Transaction, name: &str) -> Result<Option<i64>> {
+    Ok(tx
+        .query_row("SELECT id FROM users WHERE name = ?1", [name], |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+fn effort(rpe: Option<f32>, rir: Option<u32>) -> Option<Effort> {
+    rpe.map(Effort::Rpe).or(rir.map(Effort::Rir))
+}
+
+fn insert_set(tx: &Transaction, session_id: i64, position: usize, set: &Set) -> Result<()> {
+    let (rpe, rir) = match set.get_effort() {
+        Some(Effort::Rpe(rpe)) => (Some(rpe), None),
+        Some(Effort::Rir(rir)) => (None, Some(rir)),
+        None => (None, None),
+    };
+    tx.execute(
+        "INSERT INTO sets
+             (session_id, position, exercise, reps, weight, unit, duration, distance,
+              calories, heart_rate, kind, rpe, rir, performed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            session_id,
+            position,
+            set.get_exercise(),
+            set.get_reps(),
+            set.get_weight(),
+            set.get_unit().as_str(),
+            set.get_duration(),
+            set.get_distance(),
+            set.get_calories(),
+            set.get_heart_rate(),
+            set.get_kind().as_str(),
+            rpe,
+            rir,
+            set.get_performed_at(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Writes one profile and everything it owns, replacing whatever was stored under its name.
+fn write_user(tx: &Transaction, user: &UserProfile) -> Result<()> {
+    tx.execute(
+        "INSERT INTO users
+             (name, chosen_workout_session, chosen_exercise, next_session_id, weight_unit)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(name) DO UPDATE SET
+             chosen_workout_session = excluded.chosen_workout_session,
+             chosen_exercise = excluded.chosen_exercise,
+             next_session_id = excluded.next_session_id,
+             weight_unit = excluded.weight_unit",
+        params![
+            user.get_name(),
+            user.get_chosen_workout_session(),
+            user.get_chosen_exercise(),
+            user.get_next_session_id(),
+            user.get_weight_unit().as_str(),
+        ],
+    )?;
+    let user_id =
+        user_id(tx, user.get_name())?.ok_or(ResultError::UserNotFound(user.get_name().clone()))?;
+
+    tx.execute("DELETE FROM exercises WHERE user_id = ?1", [user_id])?;
+    for (position, exercise) in user.get_exercises().iter().enumerate() {
+        insert_exercise(tx, user_id, position, exercise)?;
+    }
+    tx.execute("DELETE FROM bodyweights WHERE user_id = ?1", [user_id])?;
+    for (position, entry) in user.get_bodyweights().iter().enumerate() {
+        insert_bodyweight(tx, user_id, position, entry)?;
+    }
+    tx.execute("DELETE FROM sessions WHERE user_id = ?1", [user_id])?;
+    for (position, session) in user.get_workouts().iter().enumerate() {
+        insert_session(tx, user_id, position, session)?;
+    }
+    Ok(())
+}
+
+/// Writes only what differs between `before`, the profile as stored, and `after`,
+/// so a typical change touches a handful of rows instead of the whole history.
+fn write_user_changes(tx: &Transaction, before: &UserProfile, after: &UserProfile) -> Result<()> {
+    if before == after {
+        return Ok(());
+    }
+    if before.get_name() != after.get_name() {
+        return write_user(tx, after);
+    }
+    let user_id = user_id(tx, after.get_name())?
+        .ok_or(ResultError::UserNotFound(after.get_name().clone()))?;
+
+    if (
+        before.get_chosen_workout_session(),
+        before.get_chosen_exercise(),
+        before.get_next_session_id(),
+        before.get_weight_unit(),
+    ) != (
+        after.get_chosen_workout_session(),
+        after.get_chosen_exercise(),
+        after.get_next_session_id(),
+        after.get_weight_unit(),
+    ) {
+        tx.execute(
+            "UPDATE users SET chosen_workout_session = ?2, chosen_exercise = ?3,
+                 next_session_id = ?4, weight_unit = ?5
+             WHERE id = ?1",
+            params![
+                user_id,
+                after.get_chosen_workout_session(),
+                after.get_chosen_exercise(),
+                after.get_next_session_id(),
+                after.get_weight_unit().as_str(),
+            ],
+        )?;
+    }
+
+    for (position, change) in changes(before.get_exercises(), after.get_exercises()) {
+        tx.execute(
+            "DELETE FROM exercises WHERE user_id = ?1 AND position = ?2",
+            params![user_id, position],
+        )?;
+        if let Some(exercise) = change {
+            insert_exercise(tx, user_id, position, exercise)?;
+        }
+    }
+    for (position, change) in changes(before.get_bodyweights(), after.get_bodyweights()) {
+        tx.execute(
+            "DELETE FROM bodyweights WHERE user_id = ?1 AND position = ?2",
+            params![user_id, position],
+        )?;
+        if let Some(entry) = change {
+            insert_bodyweight(tx, user_id, position, entry)?;
+        }
+    }
+    // sessions are matched by their number, which stays the same when others are
+    // deleted; removed ones go first so no other row ever takes their place
+    for stored in before.get_workouts() {
+        if after.get_workout(stored.get_id()).is_none() {
+            tx.execute(
+                "DELETE FROM sessions WHERE user_id = ?1 AND number = ?2",
+                params![user_id, stored.get_id()],
+            )?;
+        }
+    }
+    for (position, session) in after.get_workouts().iter().enumerate() {
+        let stored = before
+            .get_workouts()
+            .iter()
+            .position(|stored| stored.get_id() == session.get_id());
+        match stored {
+            Some(old_position)
+                if old_position == position && &before.get_workouts()[old_position] == session => {}
+            Some(old_position) => {
+                let stored = &before.get_workouts()[old_position];
+                update_session(tx, user_id, position, stored, session)?;
+            }
+            None => insert_session(tx, user_id, position, session)?,
+        }
+    }
+    Ok(())
+}
+
+/// The positions at which `after` differs from `before`, with the new item, or
+/// `None` where `after` is shorter.
+fn changes<'a, T: PartialEq>(before: &[T], after: &'a [T]) -> Vec<(usize, Option<&'a T>)> {
+    (0..before.len().max(after.len()))
+        .filter(|&position| before.get(position) != after.get(position))
+        .map(|position| (position, after.get(position)))
+        .collect()
+}
+
+/// Updates the stored session with `after`'s number in place, moving it to
+/// `position` and writing only the sets that changed.
+fn update_session(
+    tx: &Transaction,
+    user_id: i64,
+    position: usize,
+    before: &WorkoutSession,
+    after: &WorkoutSession,
+) -> Result<()> {
+    let session_id: i64 = tx.query_row(
+        "SELECT id FROM sessions WHERE user_id = ?1 AND number = ?2",
+        params![user_id, after.get_id()],
+        |row| row.get(0),
+    )?;
+    tx.execute(
+        "UPDATE sessions SET position = ?2, title = ?3, started_at = ?4, ended_at = ?5
+         WHERE id = ?1",
+        params![
+            session_id,
+            position,
+            after.get_title(),
+            after.get_started_at(),
+            after.get_ended_at(),
+        ],
+    )?;
+    for (position, change) in changes(before.get_sets(), after.get_sets()) {
+        tx.execute(
+            "DELETE FROM sets WHERE session_id = ?1 AND position = ?2",
+            params![session_id, position],
+        )?;
+        if let Some(set) = change {
+            insert_set(tx, session_id, position, set)?;
+        }
+    }
+    Ok(())
+}
+
+fn insert_exercise(
+    tx: &Transaction,
+    user_id: i64,
+    position: usize,
+    exercise: &Exercise,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO exercises
+             (user_id, position, name, description, equipment, loading, archived)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            user_id,
+            position,
+            exercise.get_name(),
+            exercise.get_description(),
+            exercise.get_equipment().as_str(),
+            exercise.get_loading().as_str(),
+            exercise.is_archived(),
+        ],
+    )?;
+    let exercise_id = tx.last_insert_rowid();
+    let muscles = [
+        ("primary", exercise.get_primary_muscles()),
+        ("secondary", exercise.get_secondary_muscles()),
+    ];
+    for (role, muscles) in muscles {
+        for (position, muscle) in muscles.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO exercise_muscle_groups (exercise_id, position, muscle_group, role)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![exercise_id, position, muscle.as_str(), role],
+            )?;
+        }
+    }
+    for (position, alias) in exercise.get_aliases().iter().enumerate() {
+        tx.execute(
+            "INSERT INTO exercise_aliases (exercise_id, position, alias)
+             VALUES (?1, ?2, ?3)",
+            params![exercise_id, position, alias],
+        )?;
+    }
+    for (position, metric) in exercise.get_metrics().iter().enumerate() {
+        tx.execute(
+            "INSERT INTO exercise_metrics (exercise_id, position, metric)
+             VALUES (?1, ?2, ?3)",
+            params![exercise_id, position, metric.as_str()],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_bodyweight(
+    tx: &Transaction,
+    user_id: i64,
+    position: usize,
+    entry: &BodyweightEntry,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO bodyweights (user_id, position, recorded_at, weight)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            user_id,
+            position,
+            entry.get_recorded_at(),
+            entry.get_weight()
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_session(
+    tx: &Transaction,
+    user_id: i64,
+    position: usize,
+    session: &WorkoutSession,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO sessions (user_id, position, number, title, started_at, ended_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            user_id,
+            position,
+            session.get_id(),
+            session.get_title(),
+            session.get_started_at(),
+            session.get_ended_at(),
+        ],
+    )?;
+    let session_id = tx.last_insert_rowid();
+    for (position, set) in session.get_sets().iter().enumerate() {
+        insert_set(tx, session_id, position, set)?;
+    }
+    Ok(())
+}
+
+/// Replaces every stored profile with `users`.
+fn write_users(tx: &Transaction, users: &Users) -> Result<()> {
+    let stored = {
+        let mut statement = tx.prepare("SELECT name FROM users")?;
+        let names = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        names
+    };
+    for name in stored {
+        if !users.user_exists(&name) {
+            tx.execute("DELETE FROM users WHERE name = ?1", [&name])?;
+        }
+    }
+    for user in users.list() {
+        write_user(tx, user)?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn load_users(&self) -> Result<Users> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, name FROM users ORDER BY id")?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut users = Users::new();
+        for (id, name) in rows {
+            users.add_user(self.load_user(id, name)?);
+        }
+        Ok(users)
+    }
+
+    fn save_users(&self, users: &Users) -> Result<()> {
+        let tx = self.begin()?;
+        write_users(&tx, users)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_current_user(&self) -> Result<Option<String>> {
+        Ok(self
+            .connection
+            .query_row("SELECT name FROM current_user WHERE id = 1", [], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn save_current_user(&self, name: &str) -> Result<()> {
+        let tx = self.begin()?;
+        if user_id(&tx, name)?.is_none() {
+            return Err(ResultError::UserNotFound(name.to_string()));
+        }
+        tx.execute(
+            "INSERT INTO current_user (id, name) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET name = excluded.name",
+            [name],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_user(&self, user: &UserProfile) -> Result<()> {
+        let tx = self.begin()?;
+        write_user(&tx, user)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_users(&self, f: &mut dyn FnMut(&mut Users) -> Result<()>) -> Result<()> {
+        let tx = self.begin()?;
+        let before = self.load_users()?;
+        let mut users = before.clone();
+        f(&mut users)?;
+        for user in before.list() {
+            if !users.user_exists(user.get_name()) {
+                tx.execute("DELETE FROM users WHERE name = ?1", [user.get_name()])?;
+            }
+        }
+        for user in users.list() {
+            match before.get_user(user.get_name()) {
+                Some(stored) => write_user_changes(&tx, stored, user)?,
+                None => write_user(&tx, user)?,
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_user(
+        &self,
+        name: &str,
+        f: &mut dyn FnMut(&mut UserProfile) -> Result<()>,
+    ) -> Result<()> {
+        let tx = self.begin()?;
+        let id = user_id(&tx, name)?.ok_or(ResultError::UserNotFound(name.to_string()))?;
+        let before = self.load_user(id, name.to_string())?;
+        let mut user = before.clone();
+        f(&mut user)?;
+        write_user_changes(&tx, &before, &user)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn add_set(
+        &self,
+        user: &str,
+        f: &mut dyn FnMut(&UserProfile) -> Result<(u32, Set)>,
+    ) -> Result<Set> {
+        let tx = self.begin()?;
+        let user_id = user_id(&tx, user)?.ok_or(ResultError::UserNotFound(user.to_string()))?;
+        let (session, set) = f(&self.load_user(user_id, user.to_string())?)?;
+        let session_id: i64 = tx
+            .query_row(
+                "SELECT id FROM sessions WHERE user_id = ?1 AND number = ?2",
+                params![user_id, session],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(ResultError::SessionNotFound(session.to_string()))?;
+        let position: usize = tx.query_row(
+            "SELECT COUNT(*) FROM sets WHERE session_id = ?1",
+            [session_id],
+            |row| row.get(0),
+        )?;
+
+        insert_set(&tx, session_id, position, &set)?;
+        tx.commit()?;
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set::SetEdit;
+
+    fn sample_user() -> UserProfile {
+        let mut user = UserProfile::new("test".to_string());
+        user.set_weight_unit(WeightUnit::Lb);
+        user.add_bodyweight(BodyweightEntry::new(chrono::Utc::now(), 82.5));
+        user.add_exercise(
+            Exercise::new(
+                "squat".to_string(),
+                Some("back squat".to_string()),
+                vec![MuscleGroup::Quads, MuscleGroup::Glutes],
+                Equipment::Barbell,
+            )
+            .with_secondary_muscles(vec![MuscleGroup::Hamstrings])
+            .with_aliases(vec!["back squat".to_string()]),
+        );
+        user.add_exercise(
+            Exercise::new("dip".to_string(), None, vec![], Equipment::Bodyweight)
+                .with_loading(Loading::BodyweightPlus),
+        );
+        user.get_exercise_mut("dip").unwrap().set_archived(true);
+        user.add_exercise(
+            Exercise::new("row".to_string(), None, vec![], Equipment::Machine).with_metrics(vec![
+                Metric::Distance,
+                Metric::Duration,
+                Metric::HeartRate,
+            ]),
+        );
+        let mut session = WorkoutSession::new(1, Some("Legs".to_string()), Vec::new());
+        session.add_set(
+            Set::new("squat".to_string(), 5, Weight::kg(100.0), SetKind::Normal)
+                .with_effort(Some(Effort::Rpe(8.5))),
+        );
+        session.add_set(
+            Set::new("squat".to_string(), 8, Weight::kg(80.0), SetKind::Dropset)
+                .with_effort(Some(Effort::Rir(0))),
+        );
+        let row = SetValues {
+            duration: Some(480),
+            distance: Some(2000.0),
+            heart_rate: Some(152),
+            ..SetValues::default()
+        };
+        session.add_set(Set::with_values("row".to_string(), &row, SetKind::Normal));
+        session.finish(chrono::Utc::now()).unwrap();
+        user.add_workout(session);
+        user.set_chosen_workout_session(1);
+        user.set_chosen_exercise("squat".to_string());
+        user
+    }
+
+    #[test]
+    fn test_save_and_load_users() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let mut users = Users::new();
+        users.add_user(sample_user());
+        users.add_user(UserProfile::new("other".to_string()));
+
+        storage.save_users(&users).unwrap();
+        assert_eq!(storage.load_users(), Ok(users.clone()));
+
+        users.delete_user("other".to_string()).unwrap();
+        storage.save_users(&users).unwrap();
+        assert_eq!(storage.load_users(), Ok(users));
+    }
+
+    #[test]
+    fn test_current_user() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        assert_eq!(storage.load_current_user(), Ok(None));
+        assert!(storage.save_current_user("test").is_err());
+
+        storage.save_user(&sample_user()).unwrap();
+        storage.save_current_user("test").unwrap();
+        assert_eq!(storage.load_current_user(), Ok(Some("test".to_string())));
+    }
+
+    #[test]
+    fn test_add_set() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let mut user = sample_user();
+        storage.save_user(&user).unwrap();
+
+        let set = Set::new(
+            "squat".to_string(),
+            3,
+            Weight::new(265.0, WeightUnit::Lb),
+            SetKind::Normal,
+        );
+        let added = storage
+            .add_set("test", &mut |profile| {
+                assert_eq!(profile, &user);
+                Ok((1, set.clone()))
+            })
+            .unwrap();
+        assert_eq!(added, set);
+        user.get_workout_mut(1).unwrap().add_set(set);
+
+        let users = storage.load_users().unwrap();
+        assert_eq!(users.get_user(&"test".to_string()), Some(&user));
+
+        // nothing is written when the check fails
+        assert!(storage
+            .add_set("test", &mut |_| Err(ResultError::NoChosenSession))
+            .is_err());
+        assert!(storage
+            .add_set("test", &mut |profile| Ok((
+                9,
+                profile.get_workouts()[0].get_sets()[0].clone()
+            )))
+            .is_err());
+        assert_eq!(storage.load_users(), Ok(users));
+    }
+
+    fn row_ids(storage: &SqliteStorage, table: &str) -> Vec<i64> {
+        let mut statement = storage
+            .connection
+            .prepare(&format!("SELECT id FROM {} ORDER BY id", table))
+            .unwrap();
+        let ids = statement
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<i64>>>()
+            .unwrap();
+        ids
+    }
+
+    #[test]
+    fn test_update_user_writes_only_changes() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let mut users = Users::new();
+        users.add_user(sample_user());
+        users.add_user(UserProfile::new("other".to_string()));
+        storage.save_users(&users).unwrap();
+        let exercises = row_ids(&storage, "exercises");
+        let sessions = row_ids(&storage, "sessions");
+        let sets = row_ids(&storage, "sets");
+
+        // choosing, finishing and editing the second set leave every other row alone
+        storage
+            .update_user("test", &mut |user| {
+                user.clear_chosen_exercise();
+                let session = user.get_workout_mut(1).unwrap();
+                session.get_set_mut(2).unwrap().apply(SetEdit {
+                    kind: Some(SetKind::Failure),
+                    ..SetEdit::default()
+                });
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(row_ids(&storage, "exercises"), exercises);
+        assert_eq!(row_ids(&storage, "sessions"), sessions);
+        let after_edit = row_ids(&storage, "sets");
+        assert_eq!(after_edit[0], sets[0]);
+        assert_eq!(after_edit[1], sets[2]);
+        assert_ne!(after_edit[2], sets[1]);
+
+        // deleting the first set and an exercise in the middle shifts the rest
+        let mut expected = storage.load_users().unwrap();
+        let mut session = WorkoutSession::new(2, None, Vec::new());
+        session.finish(chrono::Utc::now()).unwrap();
+        let change = |user: &mut UserProfile| {
+            user.get_workout_mut(1).unwrap().remove_set(1);
+            user.remove_exercise("dip");
+            user.add_workout(session.clone());
+        };
+        change(expected.get_user_mut(&"test".to_string()).unwrap());
+        storage
+            .update_user("test", &mut |user| {
+                change(user);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(storage.load_users(), Ok(expected.clone()));
+        assert_eq!(row_ids(&storage, "exercises")[0], exercises[0]);
+
+        // profiles a change doesn't touch aren't written at all
+        let other: Vec<i64> = row_ids(&storage, "users");
+        storage
+            .update_users(&mut |users| {
+                users.add_user(UserProfile::new("third".to_string()));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(row_ids(&storage, "users")[..2], other[..]);
+        assert_eq!(row_ids(&storage, "sessions").len(), 2);
+    }
+
+    #[test]
+    fn test_delete_first_session() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let mut user = sample_user();
+        for id in [2, 3] {
+            let mut session = WorkoutSession::new(id, Some(format!("day {}", id)), Vec::new());
+            session.add_set(Set::new(
+                "dip".to_string(),
+                id,
+                Weight::new(10.0, WeightUnit::Kg),
+                SetKind::Normal,
+            ));
+            user.add_workout(session);
+        }
+        storage.save_user(&user).unwrap();
+        let sessions = row_ids(&storage, "sessions");
+
+        storage
+            .update_user("test", &mut |user| {
+                user.remove_workout(1);
+                Ok(())
+            })
+            .unwrap();
+        user.remove_workout(1);
+        let users = storage.load_users().unwrap();
+        assert_eq!(users.get_user(&"test".to_string()), Some(&user));
+        assert_eq!(row_ids(&storage, "sessions"), sessions[1..]);
+    }
+
+    #[test]
+    fn test_migrate_from_first_schema() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO users (id, name, chosen_workout_session) VALUES (1, 'test', '01-02-2024');
+                 INSERT INTO exercises (user_id, position, name, description, equipment) VALUES
+                     (1, 0, 'squat', '', 'Barbell');
+                 INSERT INTO exercise_muscle_groups (exercise_id, position, muscle_group) VALUES
+                     (1, 0, 'Legs'), (1, 1, 'glutes');
+                 INSERT INTO sessions (user_id, position, name) VALUES
+                     (1, 0, '01-01-2024'), (1, 1, '01-02-2024'), (1, 2, '01-02-2024');
+                 INSERT INTO sets (session_id, position, exercise, reps, weight, is_dropset) VALUES
+                     (1, 0, 'squat', 5, 100.0, 0), (1, 1, 'squat', 8, 80.0, 1);",
+            )
+            .unwrap();
+
+        let storage = SqliteStorage::from_connection(connection).unwrap();
+        let users = storage.load_users().unwrap();
+        let user = users.get_user(&"test".to_string()).unwrap();
+        let ids: Vec<u32> = user.get_workouts().iter().map(|w| w.get_id()).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(user.get_chosen_workout_session(), Some(2));
+        assert_eq!(user.get_next_session_id(), 4);
+        assert_eq!(
+            user.get_workouts()[0].get_started_at().to_rfc3339(),
+            "2024-01-01T12:00:00+00:00"
+        );
+        let kinds: Vec<SetKind> = user.get_workouts()[0]
+            .get_sets()
+            .iter()
+            .map(|set| set.get_kind())
+            .collect();
+        assert_eq!(kinds, vec![SetKind::Normal, SetKind::Dropset]);
+        assert_eq!(
+            user.get_workouts()[0].get_sets()[1].get_weight(),
+            Some(80.0)
+        );
+        let squat = &user.get_exercises()[0];
+        assert_eq!(squat.get_metrics(), &Metric::DEFAULT.to_vec());
+        assert_eq!(squat.get_primary_muscles(), &vec![MuscleGroup::Glutes]);
+        assert_eq!(squat.get_equipment(), Equipment::Barbell);
+        assert_eq!(squat.get_description(), "Muscle groups: Legs");
+    }
+
+    #[test]
+    fn test_import() {
+        let json = crate::storage::MemoryStorage::new();
+        let mut users = Users::new();
+        users.add_user(sample_user());
+        json.save_users(&users).unwrap();
+        json.save_current_user("test").unwrap();
+
+        let sqlite = SqliteStorage::open_in_memory().unwrap();
+        assert_eq!(crate::storage::import(&json, &sqlite), Ok(1));
+        assert_eq!(sqlite.load_users(), Ok(users));
+        assert_eq!(sqlite.load_current_user(), Ok(Some("test".to_string())));
+
+        // a second import would merge two histories
+        assert!(crate::storage::import(&json, &sqlite).is_err());
+    }
+}
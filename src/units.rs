@@ -0,0 +1,298 @@
+//! Weight units. Sets store their weight in kilograms and remember the unit it was
+//! entered in; everything shown to the user is converted to the profile's preference.
+//!
+//! Durations and distances are stored as seconds and metres.
+
+use crate::errors::Result;
+use crate::errors::ResultError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// One pound in kilograms, exactly.
+pub const KG_PER_LB: f32 = 0.453_592_37;
+/// One mile in metres, exactly.
+pub const METRES_PER_MILE: f32 = 1_609.344;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightUnit {
+    #[default]
+    Kg,
+    Lb,
+}
+
+impl WeightUnit {
+    pub const ALL: [WeightUnit; 2] = [WeightUnit::Kg, WeightUnit::Lb];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WeightUnit::Kg => "kg",
+            WeightUnit::Lb => "lb",
+        }
+    }
+    /// Accepts `kg`/`kgs` and `lb`/`lbs`, ignoring case.
+    pub fn from_name(name: &str) -> Option<WeightUnit> {
+        match name.to_ascii_lowercase().as_str() {
+            "kg" | "kgs" => Some(WeightUnit::Kg),
+            "lb" | "lbs" => Some(WeightUnit::Lb),
+            _ => None,
+        }
+    }
+    pub fn to_kg(&self, value: f32) -> f32 {
+        match self {
+            WeightUnit::Kg => value,
+            WeightUnit::Lb => value * KG_PER_LB,
+        }
+    }
+    pub fn from_kg(&self, kg: f32) -> f32 {
+        match self {
+            WeightUnit::Kg => kg,
+            WeightUnit::Lb => kg / KG_PER_LB,
+        }
+    }
+}
+
+impl fmt::Display for WeightUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A weight together with its unit, e.g. `225 lb`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Weight {
+    value: f32,
+    unit: WeightUnit,
+}
+
+impl Weight {
+    pub fn new(value: f32, unit: WeightUnit) -> Weight {
+        Weight { value, unit }
+    }
+    pub fn kg(value: f32) -> Weight {
+        Weight::new(value, WeightUnit::Kg)
+    }
+    pub fn get_value(&self) -> f32 {
+        self.value
+    }
+    pub fn get_unit(&self) -> WeightUnit {
+        self.unit
+    }
+    pub fn to_kg(&self) -> f32 {
+        self.unit.to_kg(self.value)
+    }
+    pub fn convert(&self, unit: WeightUnit) -> Weight {
+        Weight::new(unit.from_kg(self.to_kg()), unit)
+    }
+}
+
+impl fmt::Display for Weight {
+    /// Rounds to two decimals so conversions don't print as `224.99998 lb`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // adding 0.0 turns -0.0 (e.g. an empty sum) into 0.0
+        let value = (self.value * 100.0).round() / 100.0 + 0.0;
+        write!(f, "{} {}", value, self.unit)
+    }
+}
+
+/// A weight as typed on the command line: `225lb`, `100 kg` or a bare `100`,
+/// which leaves the unit up to the profile's preference.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WeightInput {
+    pub value: f32,
+    pub unit: Option<WeightUnit>,
+}
+
+impl WeightInput {
+    pub fn parse(input: &str) -> Result<WeightInput> {
+        let input = input.trim();
+        let split = input
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(input.len());
+        let (number, unit) = input.split_at(split);
+        let invalid = || {
+            ResultError::InvalidInput(format!(
+                "{} is not a weight; use e.g. 100, 100kg or 225lb",
+                input
+            ))
+        };
+
+        let value: f32 = number.trim().parse().map_err(|_| invalid())?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(invalid());
+        }
+        let unit = match unit {
+            "" => None,
+            unit => Some(WeightUnit::from_name(unit).ok_or_else(invalid)?),
+        };
+        Ok(WeightInput { value, unit })
+    }
+    /// Fills in `default` if no unit was typed.
+    pub fn with_default_unit(&self, default: WeightUnit) -> Weight {
+        Weight::new(self.value, self.unit.unwrap_or(default))
+    }
+}
+
+/// Parses a duration in seconds: `90s`, `1m30s`, `2m`, `1h`, `1:30`, `1:02:30` or
+/// bare seconds.
+pub fn parse_duration(input: &str) -> Result<u32> {
+    let input = input.trim();
+    let invalid = || {
+        ResultError::InvalidInput(format!(
+            "{} is not a duration; use e.g. 90s, 1m30s or 1:30",
+            input
+        ))
+    };
+    let too_long = || ResultError::InvalidInput(format!("{} is too long a duration", input));
+    let number = |digits: &str| digits.parse::<u32>().map_err(|_| invalid());
+
+    if input.contains(':') {
+        let parts: Vec<&str> = input.split(':').collect();
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        return parts.iter().try_fold(0, |total: u32, part| {
+            let value = number(part)?;
+            total
+                .checked_mul(60)
+                .and_then(|total| total.checked_add(value))
+                .ok_or_else(too_long)
+        });
+    }
+    if input.chars().all(|c| c.is_ascii_digit()) {
+        return number(input);
+    }
+
+    let mut total: u32 = 0;
+    let mut rest = input.to_ascii_lowercase();
+    let mut last_factor = u32::MAX;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let value = number(&rest[..split])?;
+        let factor = match rest.as_bytes()[split] {
+            b'h' => 3600,
+            b'm' => 60,
+            b's' => 1,
+            _ => return Err(invalid()),
+        };
+        // each unit at most once, largest first
+        if factor >= last_factor {
+            return Err(invalid());
+        }
+        last_factor = factor;
+        total = value
+            .checked_mul(factor)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(too_long)?;
+        rest = rest[split + 1..].to_string();
+    }
+    Ok(total)
+}
+
+/// Parses a distance in metres: `2km`, `400m`, `1.5mi` or bare metres.
+pub fn parse_distance(input: &str) -> Result<f32> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let invalid = || {
+        ResultError::InvalidInput(format!(
+            "{} is not a distance; use e.g. 400m, 2km or 1.5mi",
+            input
+        ))
+    };
+
+    let value: f32 = number.trim().parse().map_err(|_| invalid())?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(invalid());
+    }
+    let metres_per_unit = match unit.to_ascii_lowercase().as_str() {
+        "" | "m" => 1.0,
+        "km" => 1000.0,
+        "mi" => METRES_PER_MILE,
+        _ => return Err(invalid()),
+    };
+    Ok(value * metres_per_unit)
+}
+
+/// `400 m` below a kilometre, `2.5 km` from there on.
+pub fn format_distance(metres: f32) -> String {
+    if metres < 1000.0 {
+        format!("{} m", metres.round() + 0.0)
+    } else {
+        format!("{} km", (metres / 10.0).round() / 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_weight() {
+        let parse = |input| WeightInput::parse(input).map(|x| (x.value, x.unit));
+        assert_eq!(parse("225lb"), Ok((225.0, Some(WeightUnit::Lb))));
+        assert_eq!(parse("100 KG"), Ok((100.0, Some(WeightUnit::Kg))));
+        assert_eq!(parse("62.5"), Ok((62.5, None)));
+        assert!(parse("100st").is_err());
+        assert!(parse("-5kg").is_err());
+        assert!(parse("kg").is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let weight = Weight::new(225.0, WeightUnit::Lb);
+        assert!((weight.to_kg() - 102.058).abs() < 0.001);
+        assert_eq!(weight.convert(WeightUnit::Kg).to_string(), "102.06 kg");
+        assert_eq!(
+            weight
+                .convert(WeightUnit::Kg)
+                .convert(WeightUnit::Lb)
+                .to_string(),
+            "225 lb"
+        );
+        assert_eq!(Weight::kg(-0.0).to_string(), "0 kg");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("1m30s"), Ok(90));
+        assert_eq!(parse_duration("2M"), Ok(120));
+        assert_eq!(parse_duration("1h5m"), Ok(3900));
+        assert_eq!(parse_duration("1:30"), Ok(90));
+        assert_eq!(parse_duration("1:02:30"), Ok(3750));
+        assert!(parse_duration("30s1m").is_err());
+        assert!(parse_duration("1.5m").is_err());
+        assert!(parse_duration("90x").is_err());
+        assert!(parse_duration("").is_err());
+        assert_eq!(
+            parse_duration("99999999h"),
+            Err(ResultError::InvalidInput(
+                "99999999h is too long a duration".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_duration("4294967295:00"),
+            Err(ResultError::InvalidInput(
+                "4294967295:00 is too long a duration".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_distance() {
+        assert_eq!(parse_distance("400"), Ok(400.0));
+        assert_eq!(parse_distance("400m"), Ok(400.0));
+        assert_eq!(parse_distance("2 km"), Ok(2000.0));
+        assert_eq!(parse_distance("1mi"), Ok(METRES_PER_MILE));
+        assert!(parse_distance("2 furlongs").is_err());
+        assert!(parse_distance("-1km").is_err());
+        assert_eq!(format_distance(400.0), "400 m");
+        assert_eq!(format_distance(2_345.0), "2.35 km");
+    }
+}
@@ -1,17 +1,24 @@
+use crate::bodyweight::{bodyweight_at, BodyweightEntry};
+use crate::catalog;
 use crate::errors::Result;
-use crate::errors::ResultError::OtherError;
-use crate::exercise::Exercise;
-use crate::utils::{read_file, update_file, FileContents};
+use crate::errors::{Lookup, ResultError};
+use crate::exercise::{name_taken, Exercise};
+use crate::resolve::{resolve, Candidate};
+use crate::storage::Storage;
+use crate::units::WeightUnit;
 use crate::workout_session::WorkoutSession;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-const FILE_NAME: &str = "user_profile.json";
-const CURRENT_USER_FILE_NAME: &str = "current_user.json";
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct UserProfile {
     name: String,
-    chosen_workout_session: Option<String>,
+    next_session_id: u32,
+    chosen_workout_session: Option<u32>,
     chosen_exercise: Option<String>,
+    /// The unit weights are shown in; sets are stored in kilograms regardless.
+    weight_unit: WeightUnit,
+    bodyweights: Vec<BodyweightEntry>,
     workouts: Vec<WorkoutSession>,
     created_exercises: Vec<Exercise>,
 }
@@ -20,25 +27,63 @@ impl UserProfile {
     pub fn new(name: String) -> UserProfile {
         UserProfile {
             name,
+            next_session_id: 1,
             chosen_workout_session: None,
             chosen_exercise: None,
+            weight_unit: WeightUnit::default(),
+            bodyweights: Vec::new(),
             workouts: Vec::new(),
             created_exercises: Vec::new(),
         }
     }
-    pub fn get_chosen_workout_session(&self) -> Option<&String> {
-        self.chosen_workout_session.as_ref()
+    pub fn get_chosen_workout_session(&self) -> Option<u32> {
+        self.chosen_workout_session
     }
-    pub fn set_chosen_workout_session(&mut self, workout_session: String) {
+    pub fn set_chosen_workout_session(&mut self, workout_session: u32) {
         self.chosen_workout_session = Some(workout_session);
     }
+    pub fn clear_chosen_workout_session(&mut self) {
+        self.chosen_workout_session = None;
+    }
     pub fn set_chosen_exercise(&mut self, exercise: String) {
         self.chosen_exercise = Some(exercise);
     }
     pub fn get_chosen_exercise(&self) -> Option<&String> {
         self.chosen_exercise.as_ref()
     }
+    pub fn clear_chosen_exercise(&mut self) {
+        self.chosen_exercise = None;
+    }
+    pub fn get_weight_unit(&self) -> WeightUnit {
+        self.weight_unit
+    }
+    pub fn set_weight_unit(&mut self, unit: WeightUnit) {
+        self.weight_unit = unit;
+    }
+    pub fn add_bodyweight(&mut self, entry: BodyweightEntry) {
+        self.bodyweights.push(entry);
+    }
+    pub fn get_bodyweights(&self) -> &Vec<BodyweightEntry> {
+        &self.bodyweights
+    }
+    /// The bodyweight in kilograms at `at`; see `bodyweight::bodyweight_at`.
+    pub fn get_bodyweight_at(&self, at: DateTime<Utc>) -> Option<f32> {
+        bodyweight_at(&self.bodyweights, at)
+    }
+    pub fn get_next_session_id(&self) -> u32 {
+        self.next_session_id
+    }
+    pub fn set_next_session_id(&mut self, id: u32) {
+        self.next_session_id = id;
+    }
+    /// Creates a session with a fresh id and adds it to the profile.
+    pub fn start_workout(&mut self, title: Option<String>) -> WorkoutSession {
+        let workout = WorkoutSession::new(self.next_session_id, title, Vec::new());
+        self.add_workout(workout.clone());
+        workout
+    }
     pub fn add_workout(&mut self, workout: WorkoutSession) {
+        self.next_session_id = self.next_session_id.max(workout.get_id() + 1);
         self.workouts.push(workout);
     }
     pub fn add_exercise(&mut self, exercise: Exercise) {
@@ -47,24 +92,193 @@ impl UserProfile {
     pub fn get_exercises(&self) -> &Vec<Exercise> {
         &self.created_exercises
     }
+    /// Only the profile's own exercises, by exact name; see `find_exercise`.
+    pub fn get_exercise(&self, name: &str) -> Option<&Exercise> {
+        self.created_exercises.iter().find(|x| x.get_name() == name)
+    }
+    /// The profile's exercises and the built-in ones it hasn't replaced, built-in first.
+    pub fn all_exercises(&self) -> Vec<&Exercise> {
+        let built_in = catalog::catalog()
+            .iter()
+            .map(|exercise| self.get_exercise(exercise.get_name()).unwrap_or(exercise));
+        let own = self
+            .created_exercises
+            .iter()
+            .filter(|x| catalog::find(x.get_name()).is_none());
+        built_in.chain(own).collect()
+    }
+    /// Looks `name` up by exact name, the profile's exercises before the built-in
+    /// ones, then by name or alias ignoring case.
+    pub fn find_exercise(&self, name: &str) -> Option<&Exercise> {
+        self.get_exercise(name)
+            .or_else(|| catalog::find(name))
+            .or_else(|| {
+                self.all_exercises()
+                    .into_iter()
+                    .find(|exercise| exercise.is_called(name))
+            })
+    }
+    /// The exercise a name typed by the user refers to: abbreviations and typos
+    /// are accepted as long as they point to a single exercise.
+    pub fn resolve_exercise(&self, query: &str) -> Result<&Exercise> {
+        let candidates = self
+            .all_exercises()
+            .into_iter()
+            .map(|exercise| Candidate {
+                value: exercise,
+                label: exercise.get_name().clone(),
+                names: std::iter::once(exercise.get_name())
+                    .chain(exercise.get_aliases())
+                    .map(String::as_str)
+                    .collect(),
+                keys: vec![],
+            })
+            .collect();
+        resolve(query, Lookup::Exercise, candidates)?
+            .ok_or(ResultError::ExerciseNotFound(query.to_string()))
+    }
+    /// The exercise's own name for `name`, which may be an alias but nothing
+    /// looser, since renaming or archiving the wrong exercise is hard to notice.
+    pub fn resolve_exercise_name(&self, name: &str) -> Result<String> {
+        self.find_exercise(name)
+            .map(|exercise| exercise.get_name().clone())
+            .ok_or(ResultError::ExerciseNotFound(name.to_string()))
+    }
+    /// The profile's own exercise called `name`, copying a built-in one into the
+    /// profile first so it can be changed.
+    pub fn customize_exercise(&mut self, name: &str) -> Option<&mut Exercise> {
+        if self.get_exercise(name).is_none() {
+            self.created_exercises.push(catalog::find(name)?.clone());
+        }
+        self.get_exercise_mut(name)
+    }
+    /// Any exercise, the profile's or built-in, that `name` is the name or an alias
+    /// of ignoring case, other than the exercise called `except`. Built-in exercises
+    /// count even when the profile replaced them.
+    pub fn exercise_called(&self, name: &str, except: Option<&str>) -> Option<&Exercise> {
+        self.all_exercises()
+            .into_iter()
+            .chain(catalog::catalog())
+            .filter(|exercise| Some(exercise.get_name().as_str()) != except)
+            .find(|exercise| exercise.is_called(name))
+    }
+    pub fn get_exercise_mut(&mut self, name: &str) -> Option<&mut Exercise> {
+        self.created_exercises
+            .iter_mut()
+            .find(|x| x.get_name() == name)
+    }
     pub fn remove_exercise(&mut self, name: &str) {
         self.created_exercises.retain(|x| x.get_name() != name);
     }
-    pub fn remove_workout(&mut self, workout: WorkoutSession) {
-        self.workouts.retain(|x| x != &workout);
+    /// Renames exercise `old` along with every set and the choice that refer to it.
+    /// Returns how many sets were changed. Only the profile's own exercises can be
+    /// renamed, and not to a name or alias any other exercise goes by.
+    pub fn rename_exercise(&mut self, old: &str, new: &str) -> Result<usize> {
+        if new.trim().is_empty() {
+            return Err(ResultError::InvalidInput(
+                "an exercise name can't be empty".to_string(),
+            ));
+        }
+        let old = &self.resolve_exercise_name(old)?;
+        if catalog::find(old).is_some() {
+            return Err(ResultError::Unsupported(format!(
+                "{} is a built-in exercise; create your own and archive it instead",
+                old
+            )));
+        }
+        if let Some(existing) = self.exercise_called(new, Some(old)) {
+            return Err(name_taken(new, existing));
+        }
+        self.get_exercise_mut(old)
+            .ok_or(ResultError::ExerciseNotFound(old.clone()))?
+            .set_name(new.to_string());
+        if self.chosen_exercise.as_deref() == Some(old) {
+            self.chosen_exercise = Some(new.to_string());
+        }
+        Ok(self
+            .workouts
+            .iter_mut()
+            .map(|workout| workout.rename_exercise(old, new))
+            .sum())
+    }
+    /// How many logged sets, across all sessions, are of `exercise`.
+    pub fn count_sets_of(&self, exercise: &str) -> usize {
+        self.workouts
+            .iter()
+            .flat_map(|workout| workout.get_sets())
+            .filter(|set| set.get_exercise() == exercise)
+            .count()
+    }
+    pub fn remove_workout(&mut self, id: u32) {
+        self.workouts.retain(|x| x.get_id() != id);
+    }
+    pub fn replace_workout(&mut self, id: u32, workout: WorkoutSession) -> Result<()> {
+        let existing = self
+            .get_workout_mut(id)
+            .ok_or(ResultError::SessionNotFound(id.to_string()))?;
+        *existing = workout;
+        Ok(())
     }
     pub fn get_workouts(&self) -> &Vec<WorkoutSession> {
         &self.workouts
     }
+    pub fn get_workout(&self, id: u32) -> Option<&WorkoutSession> {
+        self.workouts.iter().find(|x| x.get_id() == id)
+    }
+    pub fn get_workout_mut(&mut self, id: u32) -> Option<&mut WorkoutSession> {
+        self.workouts.iter_mut().find(|x| x.get_id() == id)
+    }
+    /// Finds a session by id, then by title or date, see `resolve`. Dates are
+    /// matched in full or as a prefix only. A title or date shared by several
+    /// sessions is an error listing all of them.
+    pub fn find_workout(&self, query: &str) -> Result<&WorkoutSession> {
+        if let Some(workout) = query.parse().ok().and_then(|id| self.get_workout(id)) {
+            return Ok(workout);
+        }
+
+        let dates: Vec<String> = self.workouts.iter().map(|x| x.get_date()).collect();
+        let candidates = self
+            .workouts
+            .iter()
+            .zip(&dates)
+            .map(|(workout, date)| Candidate {
+                value: workout,
+                label: workout.to_string(),
+                names: workout
+                    .get_title()
+                    .map(String::as_str)
+                    .into_iter()
+                    .collect(),
+                keys: vec![date.as_str()],
+            })
+            .collect();
+        resolve(query, Lookup::Session, candidates)?
+            .ok_or(ResultError::SessionNotFound(query.to_string()))
+    }
     pub fn get_name(&self) -> &String {
         &self.name
     }
-    pub fn save(&self) -> Result<()> {
-        save_user_profile(self)
+    pub fn save(&self, storage: &dyn Storage) -> Result<()> {
+        save_user_profile(storage, self)
     }
 }
 
+/// Which profile is active. Stored on its own so switching profiles doesn't copy one.
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CurrentUser {
+    name: String,
+}
+
+impl CurrentUser {
+    pub fn new(name: String) -> CurrentUser {
+        CurrentUser { name }
+    }
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct Users {
     users: Vec<UserProfile>,
 }
@@ -91,127 +305,149 @@ impl Users {
         false
     }
 
+    /// Adds the profile, or replaces the stored one with the same name.
+    pub fn upsert_user(&mut self, user: UserProfile) {
+        match self
+            .users
+            .iter_mut()
+            .find(|x| x.get_name() == user.get_name())
+        {
+            Some(existing) => *existing = user,
+            None => self.users.push(user),
+        }
+    }
+
     pub fn delete_user(&mut self, name: String) -> Result<()> {
         if let Some(index) = self.users.iter().position(|user| user.get_name() == &name) {
             self.users.remove(index);
             Ok(())
         } else {
-            Err(OtherError("User does not exist".to_string()))
+            Err(ResultError::UserNotFound(name))
         }
     }
 
     pub fn get_user(&self, name: &String) -> Option<&UserProfile> {
-        for user in self.users.iter() {
-            if user.get_name() == name {
-                return Some(user);
-            }
-        }
-        None
+        self.users.iter().find(|user| user.get_name() == name)
     }
-}
 
-pub fn read_profiles() -> Result<Users> {
-    let contents = read_file(FILE_NAME)?;
-    match contents {
-        FileContents::Users(users) => Ok(users),
-        _ => Err(OtherError("Unable to read user profiles".to_string())),
+    /// The profile a name typed by the user refers to, see `resolve`.
+    pub fn find_user(&self, query: &str) -> Result<&UserProfile> {
+        let candidates = self
+            .users
+            .iter()
+            .map(|user| Candidate {
+                value: user,
+                label: user.get_name().clone(),
+                names: vec![user.get_name().as_str()],
+                keys: vec![],
+            })
+            .collect();
+        resolve(query, Lookup::Profile, candidates)?
+            .ok_or(ResultError::UserNotFound(query.to_string()))
     }
-}
 
-pub fn save_user_profile(user_profile: &UserProfile) -> Result<()> {
-    let mut users = match read_profiles() {
-        Ok(users) => users,
-        Err(e) => Err(e)?,
-    };
-
-    let user_exists = users.user_exists(user_profile.get_name());
-
-    if !user_exists {
-        users.add_user(user_profile.clone());
-    } else {
-        let user = users
-            .get_user(user_profile.get_name())
-            .ok_or(OtherError("User does not exist".to_string()))?;
-        let index = users
-            .list()
-            .iter()
-            .position(|x| x == user)
-            .ok_or(OtherError("User does not exist".to_string()))?;
-        users.users[index] = user_profile.clone();
+    pub fn get_user_mut(&mut self, name: &String) -> Option<&mut UserProfile> {
+        self.users.iter_mut().find(|user| user.get_name() == name)
     }
+}
 
-    let file_contents = FileContents::Users(users);
-    update_file(FILE_NAME, &file_contents)
+pub fn read_profiles(storage: &dyn Storage) -> Result<Users> {
+    storage.load_users()
 }
 
-pub fn create_profile(name: String) -> Result<()> {
-    let user_profile = UserProfile::new(name);
-    save_user_profile(&user_profile)
+pub fn save_user_profile(storage: &dyn Storage, user_profile: &UserProfile) -> Result<()> {
+    storage.save_user(user_profile)
 }
 
-pub fn delete_profile(name: String) -> Result<()> {
-    let mut users = read_profiles().expect("Unable to read user profiles");
-    users
-        .delete_user(name)
-        .expect("Unable to delete user profile");
+pub fn create_profile(storage: &dyn Storage, name: String) -> Result<()> {
+    storage.update_users(&mut |users| {
+        if users.user_exists(&name) {
+            return Err(ResultError::UserAlreadyExists(name.clone()));
+        }
+        users.add_user(UserProfile::new(name.clone()));
+        Ok(())
+    })
+}
 
-    let file_contents = FileContents::Users(users);
-    update_file(FILE_NAME, &file_contents)
+pub fn delete_profile(storage: &dyn Storage, name: String) -> Result<()> {
+    storage.update_users(&mut |users| users.delete_user(name.clone()))
 }
 
-pub fn get_current_user() -> Result<UserProfile> {
-    // {CURRENT_USER_FILE_NAME} doesn't store any data other than the name of the current user
-    // so, we need to read both both files and find the user profile that matches the name
+pub fn get_current_user(storage: &dyn Storage) -> Result<UserProfile> {
+    // the storage only keeps the name of the current user,
+    // so we need to look up the matching profile
 
-    let users = read_profiles().expect("Unable to read user profiles");
-    let contents = read_file(CURRENT_USER_FILE_NAME)?;
+    let users = read_profiles(storage)?;
+    let name = storage
+        .load_current_user()?
+        .ok_or(ResultError::NoCurrentUser)?;
 
-    match contents {
-        FileContents::UserProfile(user_profile) => {
-            for user in users.list() {
-                if user.get_name() == user_profile.get_name() {
-                    return Ok(user.clone());
-                }
-            }
+    users
+        .get_user(&name)
+        .cloned()
+        .ok_or(ResultError::UserNotFound(name))
+}
 
-            Err(OtherError("Unable to find current user".to_string()))
-        }
-        _ => Err(OtherError("Unable to read current user".to_string())),
-    }
+/// Applies `f` to the current user's profile and saves it. The storage stays locked
+/// from load to save, so concurrent commands can't overwrite each other's changes.
+pub fn update_current_user<T>(
+    storage: &dyn Storage,
+    mut f: impl FnMut(&mut UserProfile) -> Result<T>,
+) -> Result<T> {
+    let name = storage
+        .load_current_user()?
+        .ok_or(ResultError::NoCurrentUser)?;
+
+    let mut output = None;
+    storage.update_user(&name, &mut |user| {
+        output = Some(f(user)?);
+        Ok(())
+    })?;
+    output.ok_or(ResultError::UserNotFound(name))
 }
 
-pub fn read_current_user() -> Result<String> {
-    let user_profile = get_current_user()?;
+/// Changes the unit the current profile shows weights in.
+pub fn set_weight_unit(storage: &dyn Storage, unit: WeightUnit) -> Result<()> {
+    update_current_user(storage, |user| {
+        user.set_weight_unit(unit);
+        Ok(())
+    })
+}
+
+pub fn read_current_user(storage: &dyn Storage) -> Result<String> {
+    let user_profile = get_current_user(storage)?;
 
     Ok(user_profile.get_name().to_string())
 }
 
-pub fn choose_profile(name: String) -> Result<()> {
-    let users = read_profiles().expect("Unable to read user profiles");
+/// Returns the chosen profile's name, which may differ from `name` if that was
+/// abbreviated or misspelled.
+pub fn choose_profile(storage: &dyn Storage, name: String) -> Result<String> {
+    let mut users = read_profiles(storage)?;
 
     if name == "default" {
         for user in users.list() {
             if user.get_name() != "default" {
-                let file_contents = FileContents::UserProfile(user.clone());
-                return update_file(CURRENT_USER_FILE_NAME, &file_contents);
+                storage.save_current_user(user.get_name())?;
+                return Ok(user.get_name().clone());
             }
         }
-        create_profile("default".to_string())?;
+        if !users.user_exists(&name) {
+            create_profile(storage, name.clone())?;
+            users = read_profiles(storage)?;
+        }
     }
 
-    let user = users
-        .get_user(&name)
-        .ok_or(OtherError("User does not exist".to_string()))?;
-
-    let file_contents = FileContents::UserProfile(user.clone());
-    update_file(CURRENT_USER_FILE_NAME, &file_contents)
+    let user = users.find_user(&name)?;
+    storage.save_current_user(user.get_name())?;
+    Ok(user.get_name().clone())
 }
 
 #[cfg(test)]
 mod tests {
     // unit tests for user_profile.rs
     use super::*;
-    use crate::errors::ResultError;
+    use crate::exercise::{Equipment, MuscleGroup};
 
     #[test]
     fn test_new() {
@@ -232,7 +468,7 @@ mod tests {
         let mut users = Users::new();
         let user = UserProfile::new("test".to_string());
         users.add_user(user.clone());
-        assert!(users.user_exists(&user.get_name()));
+        assert!(users.user_exists(user.get_name()));
     }
 
     #[test]
@@ -256,7 +492,17 @@ mod tests {
         let user = UserProfile::new("test".to_string());
         users.add_user(user.clone());
         let result = users.delete_user("test2".to_string());
-        assert!(matches!(result, Err(ResultError::OtherError(_))));
+        assert_eq!(result, Err(ResultError::UserNotFound("test2".to_string())));
+    }
+
+    #[test]
+    fn test_upsert_user() {
+        let mut users = Users::new();
+        users.upsert_user(UserProfile::new("test".to_string()));
+        let mut user = UserProfile::new("test".to_string());
+        user.set_chosen_exercise("squat".to_string());
+        users.upsert_user(user.clone());
+        assert_eq!(users.list(), &vec![user]);
     }
 
     #[test]
@@ -264,7 +510,7 @@ mod tests {
         let mut users = Users::new();
         let user = UserProfile::new("test".to_string());
         users.add_user(user.clone());
-        assert_eq!(users.get_user(&user.get_name()), Some(&user));
+        assert_eq!(users.get_user(user.get_name()), Some(&user));
     }
 
     #[test]
@@ -272,4 +518,85 @@ mod tests {
         let users = Users::new();
         assert_eq!(users.get_user(&"test".to_string()), None);
     }
+
+    #[test]
+    fn test_find_exercise() {
+        let mut user = UserProfile::new("test".to_string());
+        let built_in = user.find_exercise("OHP").unwrap();
+        assert_eq!(built_in.get_name(), "overhead press");
+        assert_eq!(user.get_exercises(), &vec![]);
+
+        // the profile's own squat replaces the built-in one
+        let squat = Exercise::new(
+            "squat".to_string(),
+            None,
+            vec![MuscleGroup::Quads],
+            Equipment::Barbell,
+        );
+        user.add_exercise(squat.clone());
+        assert_eq!(user.find_exercise("squat"), Some(&squat));
+        let squats: Vec<&Exercise> = user
+            .all_exercises()
+            .into_iter()
+            .filter(|exercise| exercise.get_name() == "squat")
+            .collect();
+        assert_eq!(squats, vec![&squat]);
+
+        assert_eq!(
+            user.resolve_exercise_name("Bench"),
+            Ok("bench press".to_string())
+        );
+        assert_eq!(
+            user.resolve_exercise_name("sled push"),
+            Err(ResultError::ExerciseNotFound("sled push".to_string()))
+        );
+        // only exact names and aliases are safe to rename or archive by
+        assert!(user.resolve_exercise_name("romanian").is_err());
+    }
+
+    #[test]
+    fn test_resolve_exercise() {
+        let user = UserProfile::new("test".to_string());
+        let name = |query| {
+            user.resolve_exercise(query)
+                .map(|exercise| exercise.get_name().as_str())
+        };
+        assert_eq!(name("romanian"), Ok("romanian deadlift"));
+        assert_eq!(name("Skull Crushr"), Ok("skull crusher"));
+        assert_eq!(name("rdl"), Ok("romanian deadlift"));
+        assert_eq!(
+            name("leg"),
+            Err(ResultError::Ambiguous {
+                lookup: Lookup::Exercise,
+                query: "leg".to_string(),
+                candidates: vec![
+                    "leg press".to_string(),
+                    "leg extension".to_string(),
+                    "leg curl".to_string(),
+                    "hanging leg raise".to_string(),
+                ]
+            })
+        );
+        assert_eq!(
+            name("sled push"),
+            Err(ResultError::ExerciseNotFound("sled push".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_find_user() {
+        let mut users = Users::new();
+        for name in ["larry", "laura", "Sam"] {
+            users.add_user(UserProfile::new(name.to_string()));
+        }
+        let name = |query| users.find_user(query).map(|user| user.get_name().as_str());
+        assert_eq!(name("sam"), Ok("Sam"));
+        assert_eq!(name("larr"), Ok("larry"));
+        assert_eq!(name("lary"), Ok("larry"));
+        assert!(matches!(name("la"), Err(ResultError::Ambiguous { .. })));
+        assert_eq!(
+            name("kim"),
+            Err(ResultError::UserNotFound("kim".to_string()))
+        );
+    }
 }
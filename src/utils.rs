@@ -1,26 +1,29 @@
-use crate::errors::ResultError::OtherError;
 use crate::errors::{FileError, Result, ResultError};
-use crate::user_profile::{UserProfile, Users};
-use std::fs::File;
+use crate::migrations;
+use crate::user_profile::{CurrentUser, Users};
+use serde_json::Value;
+use std::fs::{self, File, TryLockError};
 use std::io::prelude::*;
 
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub enum FileContents {
     Users(Users),
-    UserProfile(UserProfile),
+    CurrentUser(CurrentUser),
 }
 
 impl PartialEq for FileContents {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (FileContents::Users(users1), FileContents::Users(users2)) => users1 == users2,
-            (
-                FileContents::UserProfile(user_profile1),
-                FileContents::UserProfile(user_profile2),
-            ) => user_profile1 == user_profile2,
+            (FileContents::CurrentUser(current1), FileContents::CurrentUser(current2)) => {
+                current1 == current2
+            }
             _ => false,
         }
     }
@@ -28,12 +31,12 @@ impl PartialEq for FileContents {
 pub fn read_file(path_str: &str) -> Result<FileContents> {
     let path = Path::new(path_str);
 
-    let mut file = match File::open(&path) {
+    let mut file = match File::open(path) {
         Ok(file) => file,
         Err(e) => {
             return match e.kind() {
                 ErrorKind::NotFound => Ok(FileContents::Users(Users::new())),
-                _ => Err(OtherError(e.to_string())),
+                _ => Err(ResultError::FileError(FileError::IoError(e))),
             }
         }
     };
@@ -42,7 +45,11 @@ pub fn read_file(path_str: &str) -> Result<FileContents> {
     file.read_to_string(&mut contents)
         .map_err(|e| ResultError::FileError(FileError::IoError(e)))?;
 
-    serde_json::from_str(&contents).map_err(|e| ResultError::FileError(FileError::SerdeError(e)))
+    let mut document: Value = serde_json::from_str(&contents)
+        .map_err(|e| ResultError::FileError(FileError::SerdeError(e)))?;
+    migrations::migrate(&mut document)?;
+
+    serde_json::from_value(document).map_err(|e| ResultError::FileError(FileError::SerdeError(e)))
 }
 
 pub fn update_file(path: &str, contents: &FileContents) -> Result<()> {
@@ -51,18 +58,145 @@ pub fn update_file(path: &str, contents: &FileContents) -> Result<()> {
     // is file valid json?
     let _ = read_file(path.to_str().unwrap())?;
 
-    let contents = serde_json::to_string(&contents).map_err(FileError::SerdeError)?;
+    let mut document = serde_json::to_value(contents).map_err(FileError::SerdeError)?;
+    migrations::stamp(&mut document)?;
+    let contents = serde_json::to_string(&document).map_err(FileError::SerdeError)?;
+
+    rotate_backups(path)?;
+    write_atomically(path, contents.as_bytes())
+}
+
+/// How many previous versions of a file `update_file` keeps around.
+pub const BACKUP_COUNT: usize = 3;
+
+/// Path of the `generation`-th most recent backup of `path`, e.g. `user_profile.json.bak.1`.
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".bak.{}", generation));
+    path.with_file_name(name)
+}
+
+/// Shifts `.bak.1..N-1` up by one and copies the current file into `.bak.1`.
+/// The oldest backup falls off the end.
+fn rotate_backups(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    for generation in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, generation + 1)).map_err(FileError::IoError)?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1)).map_err(FileError::IoError)?;
+    Ok(())
+}
+
+/// Writes to a temporary file next to `path`, fsyncs it and renames it over `path`,
+/// so a crash leaves either the old or the new contents, never a truncated file.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        sync_parent_dir(path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map_err(|e| ResultError::FileError(FileError::IoError(e)))
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// How long `lock_file` waits for another process to release its lock.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An exclusive advisory lock on `<path>.lock`, released when dropped.
+///
+/// The data file itself is replaced by a rename on every write, so the lock lives
+/// in a sidecar file that stays put.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
 
-    let mut file = File::create(&path).map_err(FileError::IoError)?;
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
 
-    file.write_all(contents.as_bytes())
-        .map_err(|e| ResultError::FileError(FileError::IoError(e)))
+/// Waits up to `timeout` for an exclusive lock on `path`.
+pub fn lock_file(path: &str, timeout: Duration) -> Result<FileLock> {
+    let lock_path = lock_path(Path::new(path));
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(FileError::IoError)?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(FileLock { file }),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(LOCK_RETRY_INTERVAL)
+            }
+            Err(TryLockError::WouldBlock) => return Err(ResultError::LockTimeout(lock_path)),
+            Err(TryLockError::Error(e)) => return Err(FileError::IoError(e).into()),
+        }
+    }
+}
+
+/// Replaces `path` with its `generation`-th backup, after checking the backup is readable.
+/// The replaced contents are backed up like any other write, so the restore can be undone.
+pub fn restore_backup(path: &str, generation: usize) -> Result<()> {
+    let path = Path::new(path);
+    let backup = backup_path(path, generation);
+    if generation == 0 || generation > BACKUP_COUNT || !backup.exists() {
+        return Err(ResultError::BackupNotFound(backup));
+    }
+
+    // is backup valid json?
+    let _ = read_file(backup.to_str().unwrap())?;
+
+    // read before rotating, which moves the backup to the next generation
+    let contents = fs::read(&backup).map_err(FileError::IoError)?;
+    rotate_backups(path)?;
+    write_atomically(path, &contents)
 }
 
 #[cfg(test)]
 pub mod test_utils {
-    use crate::user_profile::{UserProfile, Users};
-    use crate::utils::{update_file, FileContents};
+    use crate::user_profile::{CurrentUser, UserProfile, Users};
+    use crate::utils::{backup_path, lock_path, update_file, FileContents, BACKUP_COUNT};
     use std::fs;
     use std::path::Path;
 
@@ -74,13 +208,27 @@ pub mod test_utils {
         if path.exists() {
             fs::remove_file(path).unwrap();
         }
+        for generation in 1..=BACKUP_COUNT {
+            let backup = backup_path(path, generation);
+            if backup.exists() {
+                fs::remove_file(backup).unwrap();
+            }
+        }
+        let lock = lock_path(path);
+        if lock.exists() {
+            fs::remove_file(lock).unwrap();
+        }
     }
 
     #[allow(dead_code)]
     pub fn create_test_user_profile() {
         let user_profile = UserProfile::new("test".to_string());
-        // save user profile in test file
-        update_file(TEST_FILE, &FileContents::UserProfile(user_profile.clone())).unwrap();
+        // save current user pointer in test file
+        update_file(
+            TEST_FILE,
+            &FileContents::CurrentUser(CurrentUser::new("test".to_string())),
+        )
+        .unwrap();
 
         let mut users = Users::new();
         users.add_user(user_profile);
@@ -92,6 +240,7 @@ pub mod test_utils {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::user_profile::UserProfile;
     use test_utils::*;
 
     // tests for read_file
@@ -144,4 +293,76 @@ mod tests {
             Err(ResultError::FileError(FileError::IoError(_)))
         ));
     }
+
+    #[test]
+    fn test_update_file_rotates_backups() {
+        let unique_path = TEST_FILE.to_string() + "_backups";
+        let path = Path::new(&unique_path);
+
+        let mut versions = Vec::new();
+        for i in 0..=BACKUP_COUNT + 1 {
+            let mut users = Users::new();
+            users.add_user(UserProfile::new(format!("test{}", i)));
+            update_file(&unique_path, &FileContents::Users(users.clone())).unwrap();
+            versions.push(users);
+        }
+
+        // the newest version is live, the previous BACKUP_COUNT are in .bak.1..N
+        let newest = versions.pop().unwrap();
+        assert_eq!(read_file(&unique_path), Ok(FileContents::Users(newest)));
+        for generation in 1..=BACKUP_COUNT {
+            let backup = backup_path(path, generation);
+            let expected = versions.pop().unwrap();
+            assert_eq!(
+                read_file(backup.to_str().unwrap()),
+                Ok(FileContents::Users(expected))
+            );
+        }
+        assert!(!backup_path(path, BACKUP_COUNT + 1).exists());
+        clear_path(&unique_path);
+    }
+
+    #[test]
+    fn test_restore_backup() {
+        let unique_path = TEST_FILE.to_string() + "_restore";
+
+        let mut users = Users::new();
+        users.add_user(UserProfile::new("test".to_string()));
+        update_file(&unique_path, &FileContents::Users(users.clone())).unwrap();
+        update_file(&unique_path, &FileContents::Users(Users::new())).unwrap();
+
+        restore_backup(&unique_path, 1).unwrap();
+        assert_eq!(
+            read_file(&unique_path),
+            Ok(FileContents::Users(users.clone()))
+        );
+
+        // the replaced contents became the newest backup, so a restore can be undone
+        restore_backup(&unique_path, 1).unwrap();
+        assert_eq!(
+            read_file(&unique_path),
+            Ok(FileContents::Users(Users::new()))
+        );
+        restore_backup(&unique_path, 1).unwrap();
+        assert_eq!(read_file(&unique_path), Ok(FileContents::Users(users)));
+
+        assert!(restore_backup(&unique_path, BACKUP_COUNT + 1).is_err());
+        clear_path(&unique_path);
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let unique_path = TEST_FILE.to_string() + "_lock";
+
+        let lock = lock_file(&unique_path, LOCK_TIMEOUT).unwrap();
+        let result = lock_file(&unique_path, Duration::from_millis(100));
+        assert_eq!(
+            result.unwrap_err(),
+            ResultError::LockTimeout(lock_path(Path::new(&unique_path)))
+        );
+
+        drop(lock);
+        assert!(lock_file(&unique_path, Duration::from_millis(100)).is_ok());
+        clear_path(&unique_path);
+    }
 }
@@ -1,26 +1,76 @@
+use crate::errors::Result;
+use crate::errors::ResultError;
 use crate::set::Set;
-use crate::user_profile::{self, save_user_profile};
-use chrono::{DateTime, Utc};
+use crate::storage::Storage;
+use crate::user_profile::{get_current_user, update_current_user, UserProfile};
+use chrono::{DateTime, Duration, Local, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct WorkoutSession {
+    /// Unique within a profile and never reused, unlike the session's date.
+    id: u32,
+    title: Option<String>,
     sets: Vec<crate::set::Set>,
-    name: String,
+    started_at: DateTime<Utc>,
+    /// `None` until the session is finished.
+    ended_at: Option<DateTime<Utc>>,
 }
 impl WorkoutSession {
-    pub fn new(sets: Vec<Set>) -> WorkoutSession {
+    pub fn new(id: u32, title: Option<String>, sets: Vec<Set>) -> WorkoutSession {
+        WorkoutSession::from_parts(id, title, Utc::now(), None, sets)
+    }
+    pub fn from_parts(
+        id: u32,
+        title: Option<String>,
+        started_at: DateTime<Utc>,
+        ended_at: Option<DateTime<Utc>>,
+        sets: Vec<Set>,
+    ) -> WorkoutSession {
         WorkoutSession {
+            id,
+            title,
             sets,
-            name: String::from(
-                DateTime::<Utc>::from(Utc::now())
-                    .format("%m-%d-%Y")
-                    .to_string(),
-            ),
+            started_at,
+            ended_at,
         }
     }
-    pub fn get_name(&self) -> &String {
-        &self.name
+    pub fn get_id(&self) -> u32 {
+        self.id
+    }
+    pub fn get_title(&self) -> Option<&String> {
+        self.title.as_ref()
+    }
+    /// The day the session started on, in the local time zone, e.g. `01-15-2024`.
+    pub fn get_date(&self) -> String {
+        self.started_at
+            .with_timezone(&Local)
+            .format("%m-%d-%Y")
+            .to_string()
+    }
+    pub fn get_started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+    pub fn get_ended_at(&self) -> Option<DateTime<Utc>> {
+        self.ended_at
+    }
+    pub fn is_finished(&self) -> bool {
+        self.ended_at.is_some()
+    }
+    /// How long the session lasted, or `None` while it is still running.
+    pub fn get_duration(&self) -> Option<Duration> {
+        self.ended_at.map(|ended_at| ended_at - self.started_at)
+    }
+    pub fn finish(&mut self, at: DateTime<Utc>) -> Result<()> {
+        if self.is_finished() {
+            return Err(ResultError::Conflict(format!(
+                "workout session #{} is already finished",
+                self.id
+            )));
+        }
+        self.ended_at = Some(at);
+        Ok(())
     }
     pub fn get_sets(&self) -> &Vec<Set> {
         &self.sets
@@ -28,132 +78,261 @@ impl WorkoutSession {
     pub fn add_set(&mut self, set: Set) {
         self.sets.push(set);
     }
+    /// Sets are numbered from 1 in the order they were added, as `display-workout` shows them.
+    pub fn get_set(&self, number: usize) -> Option<&Set> {
+        self.sets.get(number.checked_sub(1)?)
+    }
+    pub fn get_set_mut(&mut self, number: usize) -> Option<&mut Set> {
+        self.sets.get_mut(number.checked_sub(1)?)
+    }
+    /// Points the sets of exercise `old` at `new`; returns how many were changed.
+    pub fn rename_exercise(&mut self, old: &str, new: &str) -> usize {
+        let mut renamed = 0;
+        for set in self.sets.iter_mut().filter(|set| set.get_exercise() == old) {
+            set.set_exercise(new.to_string());
+            renamed += 1;
+        }
+        renamed
+    }
+    pub fn remove_set(&mut self, number: usize) -> Option<Set> {
+        self.get_set(number)?;
+        Some(self.sets.remove(number - 1))
+    }
+    pub fn remove_last_set(&mut self) -> Option<Set> {
+        self.sets.pop()
+    }
+    /// The rest taken before each set, i.e. the time since the previous one.
+    /// `None` for the first set and wherever a time wasn't recorded.
+    pub fn get_rest_times(&self) -> Vec<Option<Duration>> {
+        let mut previous = None;
+        self.sets
+            .iter()
+            .map(|set| {
+                let performed_at = set.get_performed_at();
+                let rest = previous
+                    .zip(performed_at)
+                    .map(|(before, after)| after - before);
+                previous = performed_at;
+                rest
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for WorkoutSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let started_at = self.started_at.with_timezone(&Local);
+        write!(f, "#{} {}", self.id, started_at.format("%m-%d-%Y %H:%M"))?;
+        if let Some(title) = &self.title {
+            write!(f, " ({})", title)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn start(storage: &dyn Storage, title: Option<String>) -> Result<WorkoutSession> {
+    update_current_user(storage, |user| {
+        let workout_session = user.start_workout(title.clone());
+        user.set_chosen_workout_session(workout_session.get_id());
+        Ok(workout_session)
+    })
 }
 
-pub fn start() {
-    let current_user = user_profile::read_current_user().unwrap();
-    let users = user_profile::read_profiles().unwrap();
+/// Chooses a session by id, title or date; see `UserProfile::find_workout`.
+pub fn choose(storage: &dyn Storage, query: String) -> Result<WorkoutSession> {
+    update_current_user(storage, |user| {
+        let workout_session = user.find_workout(&query)?.clone();
+        user.set_chosen_workout_session(workout_session.get_id());
+        Ok(workout_session)
+    })
+}
 
-    let mut user = users
-        .list()
-        .iter()
-        .find(|x| x.get_name() == &current_user)
-        .unwrap()
-        .clone();
+pub fn list(storage: &dyn Storage) -> Result<Vec<WorkoutSession>> {
+    let user = get_current_user(storage)?;
+    Ok(user.get_workouts().clone())
+}
 
-    let workout_session = WorkoutSession::new(Vec::new());
-    user.add_workout(workout_session.clone());
-    user.set_chosen_workout_session(workout_session.get_name().clone());
+pub(crate) fn find_current_session(user: &UserProfile) -> Result<&WorkoutSession> {
+    let current_session_id = user
+        .get_chosen_workout_session()
+        .ok_or(ResultError::NoChosenSession)?;
 
-    save_user_profile(&user).unwrap();
+    user.get_workout(current_session_id)
+        .ok_or(ResultError::SessionNotFound(current_session_id.to_string()))
+}
 
-    println!("Starting workout session");
+pub fn get_current_session(storage: &dyn Storage) -> Result<WorkoutSession> {
+    let user = get_current_user(storage)?;
+    find_current_session(&user).cloned()
 }
 
-pub fn choose(name: String) {
-    let current_user = user_profile::read_current_user().unwrap();
-    let users = user_profile::read_profiles().unwrap();
-
-    let mut user = users
-        .list()
-        .iter()
-        .find(|x| x.get_name() == &current_user)
-        .unwrap()
-        .clone();
-
-    let workout_session = user
-        .get_workouts()
-        .iter()
-        .find(|x| x.name == name)
-        .unwrap()
-        .clone();
-    user.set_chosen_workout_session(workout_session.get_name().clone());
-    save_user_profile(&user).unwrap();
-
-    println!("Chose workout session: {}", workout_session.name);
+pub fn save_current_session(storage: &dyn Storage, workout_session: &WorkoutSession) -> Result<()> {
+    update_current_user(storage, |user| {
+        let current_session_id = user
+            .get_chosen_workout_session()
+            .ok_or(ResultError::NoChosenSession)?;
+        user.replace_workout(current_session_id, workout_session.clone())
+    })
 }
-pub fn list() {
-    let current_user = user_profile::read_current_user().unwrap();
-    let users = user_profile::read_profiles().unwrap();
 
-    let user = users
-        .list()
-        .iter()
-        .find(|x| x.get_name() == &current_user)
-        .unwrap();
+/// Like `update_current_user`, but for the chosen session of the current user.
+pub fn update_current_session<T>(
+    storage: &dyn Storage,
+    mut f: impl FnMut(&mut WorkoutSession) -> Result<T>,
+) -> Result<T> {
+    update_current_user(storage, |user| {
+        let current_session_id = user
+            .get_chosen_workout_session()
+            .ok_or(ResultError::NoChosenSession)?;
+        let workout_session = user
+            .get_workout_mut(current_session_id)
+            .ok_or(ResultError::SessionNotFound(current_session_id.to_string()))?;
+        f(workout_session)
+    })
+}
 
-    for workout in user.get_workouts() {
-        println!("{}", workout.get_name());
-    }
+/// Marks the current session as finished now.
+pub fn finish(storage: &dyn Storage) -> Result<WorkoutSession> {
+    update_current_session(storage, |workout_session| {
+        workout_session.finish(Utc::now())?;
+        Ok(workout_session.clone())
+    })
 }
-pub fn get_current_session() -> WorkoutSession {
-    let current_user = user_profile::read_current_user().unwrap();
-    let users = user_profile::read_profiles().unwrap();
-
-    let user = users
-        .list()
-        .iter()
-        .find(|x| x.get_name() == &current_user)
-        .unwrap();
-
-    let workout_session = user
-        .get_workouts()
-        .iter()
-        .find(|x| x.get_name() == user.get_chosen_workout_session().clone().unwrap())
-        .unwrap();
-
-    workout_session.clone()
+
+pub fn delete(storage: &dyn Storage) -> Result<WorkoutSession> {
+    update_current_user(storage, |user| {
+        let current_session = find_current_session(user)?.clone();
+
+        user.remove_workout(current_session.get_id());
+        user.clear_chosen_workout_session();
+        Ok(current_session)
+    })
 }
-pub fn save_current_session(workout_session: &WorkoutSession) -> crate::errors::Result<()> {
-    let current_user = user_profile::read_current_user().unwrap();
-    let users = user_profile::read_profiles().unwrap();
 
-    let mut user = users
-        .list()
-        .iter()
-        .find(|x| x.get_name() == &current_user)
-        .unwrap()
-        .clone();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::Lookup;
+    use crate::set::{SetKind, SetValues};
+    use crate::storage::MemoryStorage;
+    use crate::units::Weight;
+    use crate::user_profile::{choose_profile, create_profile};
+
+    fn storage_with_user() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        create_profile(&storage, "test".to_string()).unwrap();
+        choose_profile(&storage, "test".to_string()).unwrap();
+        storage
+    }
 
-    let current_session_name = user.get_chosen_workout_session().clone().unwrap();
-    let current_session = user
-        .get_workouts()
-        .iter()
-        .find(|x| x.get_name() == current_session_name)
-        .unwrap();
+    #[test]
+    fn test_start() {
+        let storage = storage_with_user();
+        let workout_session = start(&storage, None).unwrap();
+        assert_eq!(list(&storage).unwrap(), vec![workout_session.clone()]);
+        assert_eq!(get_current_session(&storage), Ok(workout_session));
+    }
 
-    user.remove_workout(current_session.clone());
-    user.add_workout(workout_session.clone());
+    #[test]
+    fn test_start_twice_on_the_same_day() {
+        let storage = storage_with_user();
+        let first = start(&storage, None).unwrap();
+        let second = start(&storage, Some("Legs".to_string())).unwrap();
+        assert_ne!(first.get_id(), second.get_id());
+        assert_eq!(get_current_session(&storage), Ok(second.clone()));
 
+        assert_eq!(
+            choose(&storage, first.get_id().to_string()),
+            Ok(first.clone())
+        );
+        assert_eq!(choose(&storage, "legs".to_string()), Ok(second.clone()));
+        assert_eq!(choose(&storage, "Leg".to_string()), Ok(second.clone()));
+        assert!(matches!(
+            choose(&storage, first.get_date()),
+            Err(ResultError::Ambiguous {
+                lookup: Lookup::Session,
+                ..
+            })
+        ));
 
-    save_user_profile(&user)
-}
-pub fn delete() {
-    let current_user = user_profile::read_current_user().unwrap();
-    let users = user_profile::read_profiles().unwrap();
-
-    let mut user = users
-        .list()
-        .iter()
-        .find(|x| x.get_name() == &current_user)
-        .unwrap()
-        .clone();
-    let current_session = get_current_session();
-
-    user.remove_workout(current_session.clone());
-
-    save_user_profile(&user).unwrap();
-    println!("Deleted workout session: {}", current_session.get_name());
-}
+        delete(&storage).unwrap();
+        assert_eq!(list(&storage).unwrap(), vec![first]);
+    }
+
+    #[test]
+    fn test_save_current_session() {
+        let storage = storage_with_user();
+        let mut workout_session = start(&storage, None).unwrap();
+        workout_session.add_set(Set::new(
+            "squat".to_string(),
+            5,
+            Weight::kg(100.0),
+            SetKind::Normal,
+        ));
+        save_current_session(&storage, &workout_session).unwrap();
+        assert_eq!(get_current_session(&storage), Ok(workout_session));
+    }
+
+    #[test]
+    fn test_finish() {
+        let storage = storage_with_user();
+        let started = start(&storage, None).unwrap();
+        assert_eq!(started.get_duration(), None);
+
+        let finished = finish(&storage).unwrap();
+        assert_eq!(finished.get_started_at(), started.get_started_at());
+        assert!(finished.get_duration().unwrap() >= Duration::zero());
+        assert_eq!(get_current_session(&storage), Ok(finished));
+        assert!(matches!(finish(&storage), Err(ResultError::Conflict(_))));
+        assert!(matches!(
+            crate::set::create_set(
+                &storage,
+                "squat".to_string(),
+                SetValues::default(),
+                SetKind::Normal,
+                None
+            ),
+            Err(ResultError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn test_rest_times() {
+        let started_at = Utc::now();
+        let set_at = |seconds: Option<i64>| {
+            Set::new("squat".to_string(), 5, Weight::kg(100.0), SetKind::Normal)
+                .with_performed_at(seconds.map(|seconds| started_at + Duration::seconds(seconds)))
+        };
+        let workout_session = WorkoutSession::from_parts(
+            1,
+            None,
+            started_at,
+            None,
+            vec![
+                set_at(Some(0)),
+                set_at(Some(90)),
+                set_at(None),
+                set_at(Some(300)),
+            ],
+        );
+        assert_eq!(
+            workout_session.get_rest_times(),
+            vec![None, Some(Duration::seconds(90)), None, None]
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let storage = storage_with_user();
+        start(&storage, None).unwrap();
+        delete(&storage).unwrap();
+        assert_eq!(list(&storage).unwrap(), vec![]);
+        assert!(get_current_session(&storage).is_err());
+    }
 
-pub fn display() {
-    let current_session = get_current_session();
-    println!("Workout session: {}", current_session.get_name());
-    for set in current_session.get_sets() {
-        println!("Set: {}", set.get_exercise());
-        println!("Reps: {}", set.get_reps());
-        println!("Weight: {}", set.get_weight());
-        println!("Is dropset: {}", set.get_is_dropset());
-        println!();
+    #[test]
+    fn test_get_current_session_without_user() {
+        let storage = MemoryStorage::new();
+        assert!(get_current_session(&storage).is_err());
     }
 }